@@ -0,0 +1,80 @@
+//! A minimal MCP server over stdio with a single `echo` tool, for trying out `[mcp_servers]`:
+//!
+//! ```toml
+//! [mcp_servers.echo]
+//! command = "cargo"
+//! args = ["run", "--quiet", "--example", "mcp_echo"]
+//! ```
+//!
+//! Before answering a call it pings the client and waits for the reply, as servers checking
+//! the connection do.
+
+use std::io::{BufRead, Write};
+
+use serde_json::{Value, json};
+
+fn main() {
+    let stdin = std::io::stdin();
+    let mut lines = stdin.lock().lines();
+    while let Some(Ok(line)) = lines.next() {
+        let Ok(message) = serde_json::from_str::<Value>(&line) else {
+            continue;
+        };
+        // Notifications need no answer.
+        let Some(id) = message.get("id") else {
+            continue;
+        };
+        let result = match message["method"].as_str().unwrap_or_default() {
+            "initialize" => json!({
+                "protocolVersion": "2025-03-26",
+                "capabilities": { "tools": {} },
+                "serverInfo": { "name": "mcp_echo", "version": "0.1.0" },
+            }),
+            "tools/list" => json!({
+                "tools": [{
+                    "name": "echo",
+                    "description": "Echo the text back.",
+                    "inputSchema": {
+                        "type": "object",
+                        "properties": { "text": { "type": "string" } },
+                        "required": ["text"],
+                    },
+                    "annotations": { "readOnlyHint": true },
+                }]
+            }),
+            "tools/call" => {
+                send(&json!({ "jsonrpc": "2.0", "id": "ping", "method": "ping" }));
+                let pong = lines
+                    .next()
+                    .and_then(Result::ok)
+                    .and_then(|line| serde_json::from_str::<Value>(&line).ok());
+                match pong {
+                    Some(pong) if pong["id"] == "ping" && pong.get("result").is_some() => json!({
+                        "content": [{
+                            "type": "text",
+                            "text": message["params"]["arguments"]["text"].as_str().unwrap_or_default(),
+                        }]
+                    }),
+                    _ => json!({
+                        "content": [{ "type": "text", "text": "The client did not answer the ping." }],
+                        "isError": true,
+                    }),
+                }
+            }
+            method => {
+                send(&json!({
+                    "jsonrpc": "2.0",
+                    "id": id,
+                    "error": { "code": -32601, "message": format!("Unknown method {method}.") },
+                }));
+                continue;
+            }
+        };
+        send(&json!({ "jsonrpc": "2.0", "id": id, "result": result }));
+    }
+}
+
+fn send(message: &Value) {
+    let mut stdout = std::io::stdout().lock();
+    let _ = writeln!(stdout, "{message}").and_then(|_| stdout.flush());
+}
@@ -1,12 +1,55 @@
 use anyhow::Context;
 
-use super::{Cli, CommandState, SetApiKeyCommand};
+use crate::{
+    configuration::KeySource,
+    provider::{ProviderRegistry, env_var_name},
+};
+
+use super::{ApiKeyCommand, Cli, CommandState, SetApiKeyCommand};
 
 impl Cli {
+    pub(super) async fn handle_api_key_command(
+        command: ApiKeyCommand,
+        state: &CommandState<'_>,
+    ) -> anyhow::Result<()> {
+        match command {
+            ApiKeyCommand::List => Cli::list_api_keys(state),
+            ApiKeyCommand::Set(command) => Cli::handle_api_key(command, state),
+            ApiKeyCommand::Delete { provider } => {
+                ProviderRegistry::new(state.config)?.ensure_known(&provider)?;
+                state
+                    .api_key_manager
+                    .delete_api_key(&provider)
+                    .context("Failed to delete api key.")?;
+                println!("Deleted keyring api key for {provider}.");
+                Ok(())
+            }
+            ApiKeyCommand::Verify { provider } => {
+                let provider = provider.unwrap_or_else(|| state.config.provider.clone());
+                let registry = ProviderRegistry::new(state.config)?;
+                if registry.names().any(|name| name == provider)
+                    && !registry.api_key_names().any(|name| name == provider)
+                {
+                    println!("{provider} does not use an api key.");
+                    return Ok(());
+                }
+                registry
+                    .build(&provider, state.config, state.api_key_manager, None)?
+                    .verify_api_key()
+                    .await
+                    .with_context(|| format!("Api key for {provider} was not accepted."))?;
+                println!("Api key for {provider} is valid.");
+                Ok(())
+            }
+        }
+    }
+
     pub(super) fn handle_api_key(
         command: SetApiKeyCommand,
         state: &CommandState,
     ) -> anyhow::Result<()> {
+        let provider = command.provider.as_ref().unwrap_or(&state.config.provider);
+        ProviderRegistry::new(state.config)?.ensure_known(provider)?;
         let key = match command.key {
             Some(key) => Some(key),
             None => match state.cli_handler {
@@ -17,8 +60,47 @@ impl Cli {
         .context("No API key supplied")?;
         state
             .api_key_manager
-            .set_api_key(&state.config.provider, &key)
+            .set_api_key(provider, &key)
             .context("Failed to set api key.")?;
         Ok(())
     }
+
+    fn list_api_keys(state: &CommandState) -> anyhow::Result<()> {
+        let registry = ProviderRegistry::new(state.config)?;
+        for provider in registry.api_key_names() {
+            let status = match state.api_key_manager.find_api_key(provider) {
+                Ok(Some((source, key))) => {
+                    format!(
+                        "{} ({})",
+                        mask_api_key(&key),
+                        describe_source(source, provider)
+                    )
+                }
+                Ok(None) => "not set".to_string(),
+                Err(err) => format!("error: {err}"),
+            };
+            println!("{provider:<16} {status}");
+        }
+        Ok(())
+    }
+}
+
+fn describe_source(source: KeySource, provider: &str) -> String {
+    match source {
+        KeySource::Env => env_var_name(provider),
+        KeySource::File => "credentials file".to_string(),
+        KeySource::Command => "key_command".to_string(),
+        KeySource::Keyring => "keyring".to_string(),
+    }
+}
+
+/// Show just enough of a key to tell keys apart.
+fn mask_api_key(key: &str) -> String {
+    let chars = key.chars().collect::<Vec<_>>();
+    if chars.len() <= 12 {
+        return "*".repeat(chars.len());
+    }
+    let head = chars[..4].iter().collect::<String>();
+    let tail = chars[chars.len() - 4..].iter().collect::<String>();
+    format!("{head}…{tail}")
 }
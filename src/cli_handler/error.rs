@@ -1,5 +1,7 @@
 use inquire::InquireError;
 
+use crate::{configuration, provider};
+
 pub type Result<T> = std::result::Result<T, Error>;
 
 #[derive(Debug, thiserror::Error)]
@@ -7,13 +9,9 @@ pub enum Error {
     #[error("User interrupted.")]
     Interrupted,
     #[error("Error retrieving input. {0}")]
-    InquiryError(#[from] InquireError),
-    #[error("Command: {0} not an option.")]
-    CommandNotOption(String),
+    Inquiry(#[from] InquireError),
     #[error("File Handler error. {0}")]
-    FileHandlerError(#[from] anyhow::Error),
-    #[error("LLM Stream error. {0}")]
-    LLMError(anyhow::Error),
+    FileHandler(#[from] anyhow::Error),
 }
 
 pub fn map_inquire_error(err: InquireError) -> Error {
@@ -22,3 +20,39 @@ pub fn map_inquire_error(err: InquireError) -> Error {
         _ => Error::from(err),
     }
 }
+
+pub const EXIT_FAILURE: u8 = 1;
+pub const EXIT_CONFIG: u8 = 2;
+pub const EXIT_API_KEY: u8 = 3;
+pub const EXIT_NETWORK: u8 = 4;
+
+/// Map an error to the process exit code, so scripts can tell a missing key apart from a bad
+/// config apart from a network failure.
+pub fn exit_code(err: &anyhow::Error) -> u8 {
+    err.chain()
+        .find_map(|cause| {
+            if cause.is::<configuration::Error>() {
+                return Some(EXIT_CONFIG);
+            }
+            match cause.downcast_ref::<provider::error::Error>()? {
+                provider::error::Error::UnknownProvider { .. }
+                | provider::error::Error::ProviderNameTaken { .. } => Some(EXIT_CONFIG),
+                provider::error::Error::Keychain { .. }
+                | provider::error::Error::NoApiKey { .. }
+                | provider::error::Error::InsecureCredentialsFile { .. }
+                | provider::error::Error::CredentialsFile { .. }
+                | provider::error::Error::KeyCommand { .. }
+                | provider::error::Error::KeyPrompt { .. } => Some(EXIT_API_KEY),
+                provider::error::Error::Api { status, .. }
+                    if *status == reqwest::StatusCode::UNAUTHORIZED
+                        || *status == reqwest::StatusCode::FORBIDDEN =>
+                {
+                    Some(EXIT_API_KEY)
+                }
+                provider::error::Error::Network(_) | provider::error::Error::Api { .. } => {
+                    Some(EXIT_NETWORK)
+                }
+            }
+        })
+        .unwrap_or(EXIT_FAILURE)
+}
@@ -0,0 +1,179 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use glob::{MatchOptions, Pattern};
+
+use crate::provider::{ChatHistoryItem, FileUploadData};
+
+/// Files larger than this are left out, as they are rarely source the model needs to read.
+const MAX_FILE_BYTES: u64 = 256 * 1024;
+/// Upper bound for everything attached at once, to keep requests within context windows.
+const MAX_TOTAL_BYTES: u64 = 1024 * 1024;
+/// How much of a file is checked for NUL bytes when deciding whether it is binary.
+const BINARY_SNIFF_BYTES: usize = 8000;
+
+/// Files read for a `#file:` or `@` attachment, with what was left out and why.
+#[derive(Debug, Default)]
+pub struct Attachment {
+    pub files: Vec<ChatHistoryItem>,
+    bytes: u64,
+    skipped_binary: usize,
+    skipped_large: usize,
+    skipped_over_total: usize,
+}
+
+/// Attach a file, every file below a directory, or every file matching a glob such as
+/// `src/**/*.rs`. Directories and globs skip anything `.gitignore` excludes.
+pub fn attach(pattern: &str) -> anyhow::Result<Attachment> {
+    let paths = matching_paths(pattern)?;
+    if paths.is_empty() {
+        anyhow::bail!("No files match {pattern}.");
+    }
+
+    let mut attachment = Attachment::default();
+    for path in paths {
+        let size = std::fs::metadata(&path)
+            .with_context(|| format!("Failed to read metadata of {}.", path.display()))?
+            .len();
+        if size > MAX_FILE_BYTES {
+            attachment.skipped_large += 1;
+            continue;
+        }
+        if attachment.bytes + size > MAX_TOTAL_BYTES {
+            attachment.skipped_over_total += 1;
+            continue;
+        }
+        let contents = std::fs::read(&path)
+            .with_context(|| format!("Failed to read file contents of {}.", path.display()))?;
+        let text = match String::from_utf8(contents) {
+            Ok(text)
+                if !text
+                    .as_bytes()
+                    .iter()
+                    .take(BINARY_SNIFF_BYTES)
+                    .any(|b| *b == 0) =>
+            {
+                text
+            }
+            _ => {
+                attachment.skipped_binary += 1;
+                continue;
+            }
+        };
+        attachment.bytes += size;
+        attachment.files.push(
+            FileUploadData {
+                text,
+                relative_filepath: path.to_string_lossy().into_owned(),
+            }
+            .into(),
+        );
+    }
+    Ok(attachment)
+}
+
+impl Attachment {
+    /// One line describing what was attached and what was skipped.
+    pub fn summary(&self) -> String {
+        let mut summary = format!(
+            "Attached {} ({}).",
+            plural(self.files.len(), "file"),
+            format_bytes(self.bytes)
+        );
+        let skipped = [
+            (self.skipped_binary, "binary".to_string()),
+            (
+                self.skipped_large,
+                format!("larger than {}", format_bytes(MAX_FILE_BYTES)),
+            ),
+            (
+                self.skipped_over_total,
+                format!("over the {} total limit", format_bytes(MAX_TOTAL_BYTES)),
+            ),
+        ]
+        .into_iter()
+        .filter(|(count, _)| *count > 0)
+        .map(|(count, reason)| format!("{count} {reason}"))
+        .collect::<Vec<_>>();
+        if !skipped.is_empty() {
+            summary.push_str(&format!(" Skipped {}.", skipped.join(", ")));
+        }
+        summary
+    }
+
+    pub fn file_paths(&self) -> impl Iterator<Item = &str> {
+        self.files.iter().filter_map(|item| match item {
+            ChatHistoryItem::FileUpload(file) => Some(file.relative_filepath.as_str()),
+            ChatHistoryItem::Chat(_) | ChatHistoryItem::ToolCall(_) => None,
+        })
+    }
+}
+
+/// Whether `pattern` has wildcards, and is matched against paths rather than naming one.
+pub fn is_glob(pattern: &str) -> bool {
+    pattern.contains(['*', '?', '['])
+}
+
+fn matching_paths(pattern: &str) -> anyhow::Result<Vec<PathBuf>> {
+    let path = Path::new(pattern);
+    if path.is_file() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    if path.is_dir() {
+        return Ok(walk(path, None));
+    }
+    if !is_glob(pattern) {
+        anyhow::bail!("No file or directory at {pattern}.");
+    }
+
+    let glob = Pattern::new(pattern).with_context(|| format!("Invalid glob pattern {pattern}."))?;
+    // Only walk below the part of the pattern without wildcards.
+    let base = path
+        .components()
+        .take_while(|component| !is_glob(&component.as_os_str().to_string_lossy()))
+        .collect::<PathBuf>();
+    let base = if base.as_os_str().is_empty() {
+        PathBuf::from(".")
+    } else {
+        base
+    };
+    Ok(walk(&base, Some(&glob)))
+}
+
+fn walk(dir: &Path, glob: Option<&Pattern>) -> Vec<PathBuf> {
+    let options = MatchOptions {
+        require_literal_separator: true,
+        ..Default::default()
+    };
+    ignore::WalkBuilder::new(dir)
+        .sort_by_file_name(|a, b| a.cmp(b))
+        .build()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            entry
+                .file_type()
+                .is_some_and(|file_type| file_type.is_file())
+        })
+        .map(|entry| entry.into_path())
+        .map(|path| match path.strip_prefix(".") {
+            Ok(relative) if dir == Path::new(".") => relative.to_path_buf(),
+            _ => path,
+        })
+        .filter(|path| glob.is_none_or(|glob| glob.matches_path_with(path, options)))
+        .collect()
+}
+
+fn plural(count: usize, noun: &str) -> String {
+    match count {
+        1 => format!("1 {noun}"),
+        _ => format!("{count} {noun}s"),
+    }
+}
+
+pub(super) fn format_bytes(bytes: u64) -> String {
+    match bytes {
+        0..1024 => format!("{bytes} B"),
+        1024..1_048_576 => format!("{:.0} KB", bytes as f64 / 1024.0),
+        _ => format!("{:.1} MB", bytes as f64 / 1_048_576.0),
+    }
+}
@@ -0,0 +1,146 @@
+use std::path::Path;
+
+use crate::provider::{ChatHistory, ChatHistoryItem, FileUploadData};
+
+use super::attachment::format_bytes;
+
+/// Slash commands that manage attached files, shared by the `code` prompt and the TUI.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ContextCommand {
+    /// `/context`
+    List,
+    /// `/drop <path>`
+    Drop(String),
+    /// `/refresh`
+    Refresh,
+}
+
+impl ContextCommand {
+    pub fn parse(input: &str) -> Option<Self> {
+        let input = input.trim();
+        let (command, argument) = input.split_once(' ').unwrap_or((input, ""));
+        match (command, argument.trim()) {
+            ("/context", "") => Some(Self::List),
+            ("/drop", path) => Some(Self::Drop(path.to_string())),
+            ("/refresh", "") => Some(Self::Refresh),
+            _ => None,
+        }
+    }
+
+    /// Apply the command, returning a message for the user.
+    pub fn run(&self, history: &mut ChatHistory) -> anyhow::Result<String> {
+        match self {
+            Self::List => Ok(describe_context(history)),
+            Self::Drop(path) if path.is_empty() => {
+                anyhow::bail!("Give the path to drop, e.g. /drop src/main.rs.")
+            }
+            Self::Drop(path) => drop_attachment(history, path),
+            Self::Refresh => Ok(refresh_attachments(history)),
+        }
+    }
+}
+
+/// A file attached to the active branch of the conversation.
+struct AttachedFile {
+    path: String,
+    bytes: usize,
+}
+
+/// Attached files in the order they were added.
+fn attached_files(history: &ChatHistory) -> Vec<AttachedFile> {
+    history
+        .iter()
+        .filter_map(|item| match item {
+            ChatHistoryItem::FileUpload(file) => Some(AttachedFile {
+                path: file.relative_filepath.clone(),
+                bytes: file.text.len(),
+            }),
+            ChatHistoryItem::Chat(_) | ChatHistoryItem::ToolCall(_) => None,
+        })
+        .collect()
+}
+
+/// Lines listing the attached files with their sizes, and the total.
+fn describe_context(history: &ChatHistory) -> String {
+    let files = attached_files(history);
+    if files.is_empty() {
+        return "No files attached.".to_string();
+    }
+    let total = files.iter().map(|file| file.bytes).sum::<usize>();
+    let mut description = files
+        .iter()
+        .map(|file| format!("{:>9}  {}\n", format_bytes(file.bytes as u64), file.path))
+        .collect::<String>();
+    description.push_str(&format!(
+        "{:>9}  total in {} files",
+        format_bytes(total as u64),
+        files.len()
+    ));
+    description
+}
+
+/// Remove the attachment at `path`, or every attachment below it when it is a directory.
+fn drop_attachment(history: &mut ChatHistory, path: &str) -> anyhow::Result<String> {
+    let target = Path::new(path.trim());
+    let matches = |file: &FileUploadData| Path::new(&file.relative_filepath).starts_with(target);
+    let mut dropped = 0;
+    let mut index = 0;
+    while let Some(item) = history.get(index) {
+        match item {
+            ChatHistoryItem::FileUpload(file) if matches(file) => {
+                history.remove(index);
+                dropped += 1;
+            }
+            _ => index += 1,
+        }
+    }
+    match dropped {
+        0 => anyhow::bail!("No attached file matches {path}. Use /context to list attachments."),
+        1 => Ok(format!("Dropped {path} from the context.")),
+        _ => Ok(format!(
+            "Dropped {dropped} files under {path} from the context."
+        )),
+    }
+}
+
+/// Re-read every attached file, replacing the contents of any that changed on disk.
+fn refresh_attachments(history: &mut ChatHistory) -> String {
+    let mut checked = 0;
+    let mut updated = Vec::new();
+    let mut unreadable = Vec::new();
+    for index in 0..history.len() {
+        let ChatHistoryItem::FileUpload(file) = &history[index] else {
+            continue;
+        };
+        checked += 1;
+        match std::fs::read_to_string(&file.relative_filepath) {
+            Ok(text) if text == file.text => {}
+            Ok(text) => {
+                let path = file.relative_filepath.clone();
+                updated.push(path.clone());
+                history.replace(
+                    index,
+                    FileUploadData {
+                        text,
+                        relative_filepath: path,
+                    }
+                    .into(),
+                );
+            }
+            Err(err) => {
+                tracing::warn!("Failed to refresh {}: {:?}", file.relative_filepath, err);
+                unreadable.push(file.relative_filepath.clone());
+            }
+        }
+    }
+
+    let mut summary = match (checked, updated.len()) {
+        (0, _) => "No files attached.".to_string(),
+        (_, 0) => format!("All {checked} attached files are up to date."),
+        _ => format!("Refreshed {}.", updated.join(", ")),
+    };
+    if !unreadable.is_empty() {
+        summary.push_str(&format!(" Could not read {}.", unreadable.join(", ")));
+    }
+    summary
+}
@@ -3,9 +3,13 @@ use std::{env::current_dir, path::PathBuf};
 use anyhow::Context;
 use inquire::Autocomplete;
 
-use crate::provider::{ChatHistoryItem, FileUploadData};
+mod attachment;
+mod context;
 
-pub const FILE_INPUT_TRIGGER: &'static str = "#file:";
+pub use attachment::{Attachment, attach, is_glob};
+pub use context::ContextCommand;
+
+pub const FILE_INPUT_TRIGGER: &str = "#file:";
 
 #[derive(Clone)]
 pub struct FileInputHandler {
@@ -18,15 +22,6 @@ impl FileInputHandler {
             cwd: current_dir().context("Failed to get cwd for File Input Handler.")?,
         })
     }
-
-    pub fn chat_from_file(&self, path: &str) -> anyhow::Result<ChatHistoryItem> {
-        let contents = std::fs::read_to_string(path).context("Failed to read file contents.")?;
-        Ok(FileUploadData {
-            text: contents,
-            relative_filepath: path.to_owned(),
-        }
-        .into())
-    }
 }
 
 impl Autocomplete for FileInputHandler {
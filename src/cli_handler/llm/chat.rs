@@ -1,8 +1,12 @@
 use anyhow::Context;
 
-use crate::cli_handler::ratatui_app::App;
+use crate::cli_handler::{ratatui_app::App, session::ActiveSession};
 
-use super::{ChatAction, ChatCommand, Cli, output_file_added, output_response};
+use super::stream::stream_response;
+use super::{
+    ChatCommand, Cli, ask_for_tool_approval, compact_history, connect_mcp_servers, one_shot_prompt,
+    output_response, read_piped_stdin, report_prompt_tokens, report_token_usage,
+};
 use super::{CommandState, Provider};
 
 impl Cli {
@@ -11,29 +15,43 @@ impl Cli {
         state: &CommandState<'_>,
     ) -> anyhow::Result<()> {
         let mut llm_provider =
-            Provider::new(&state.config, &state.api_key_manager, state.cli_handler);
+            Provider::new(state.config, state.api_key_manager, state.cli_handler)?;
+        let stdin = read_piped_stdin(&command)?;
+        let interactive =
+            command.message.is_none() && stdin.is_none() && state.cli_handler.is_some();
+        let session = ActiveSession::open(&command, interactive, &mut llm_provider)?;
         llm_provider.merge_tools(command.get_tools());
+        connect_mcp_servers(&mut llm_provider, state).await;
 
-        match (command.message, &state.cli_handler) {
-            (None, Some(handler)) => {
-                let mut app = App::new(&mut llm_provider);
-                let mut terminal = ratatui::init();
-                let app_result = app.run(&mut terminal).await;
-                ratatui::restore();
-                app_result.context("Ratatui Terminal Error.")
-            }
-            (message, _) => {
-                let prompt = message.context("No message supplied. Use -m to pass a message.")?;
+        let result = if interactive {
+            let mut app = App::new(&mut llm_provider);
+            let mut terminal = ratatui::init();
+            let app_result = app.run(&mut terminal).await;
+            ratatui::restore();
+            app_result.context("Ratatui Terminal Error.")
+        } else {
+            let prompt = one_shot_prompt(command.message, stdin, &mut llm_provider)?;
+            ask_for_tool_approval(&mut llm_provider);
+            compact_history(&mut llm_provider, &prompt, state).await?;
+            report_prompt_tokens(&llm_provider, &prompt, state).await;
 
+            if command.no_stream {
                 let response = llm_provider
                     .complete_chat(prompt)
                     .await
                     .context("Failed to retrieve response from the LLM Provider")?;
 
                 output_response(response.as_str(), state);
-
-                Ok(())
+            } else {
+                stream_response(&mut llm_provider, prompt, state, false).await?;
             }
+            report_token_usage(&llm_provider, state);
+            Ok(())
+        };
+
+        if let Some(session) = &session {
+            session.save(&llm_provider, state)?;
         }
+        result
     }
 }
@@ -1,8 +1,16 @@
 use anyhow::Context;
 
-use crate::provider::{ChatData, Provider};
+use crate::{
+    cli_handler::{file_input::attach, session::ActiveSession},
+    provider::{ChatData, Provider},
+};
 
-use super::{ChatAction, ChatCommand, Cli, CommandState, output_response};
+use super::stream::stream_response;
+use super::{
+    ChatAction, ChatCommand, Cli, CommandState, ask_for_tool_approval, compact_history,
+    connect_mcp_servers, one_shot_prompt, output_context_message, output_files_added,
+    output_response, read_piped_stdin, report_prompt_tokens, report_token_usage,
+};
 
 impl Cli {
     pub(crate) async fn handle_code(
@@ -10,35 +18,49 @@ impl Cli {
         state: &CommandState<'_>,
     ) -> anyhow::Result<()> {
         let mut llm_provider =
-            Provider::new(&state.config, &state.api_key_manager, state.cli_handler);
+            Provider::new(state.config, state.api_key_manager, state.cli_handler)?;
+        let stdin = read_piped_stdin(&command)?;
+        let interactive =
+            command.message.is_none() && stdin.is_none() && state.cli_handler.is_some();
+        let session = ActiveSession::open(&command, interactive, &mut llm_provider)?;
         llm_provider.merge_tools(command.get_tools());
-        llm_provider.add_chat_to_context(ChatData {
-            role: crate::provider::ChatRole::System,
-            text: "The user is issuing a code generation command. You must only respond with the code you have generated.".to_string()
-        }.into())?;
+        connect_mcp_servers(&mut llm_provider, state).await;
+        ask_for_tool_approval(&mut llm_provider);
+        llm_provider.add_chat_to_context(ChatData::system(
+            "The user is issuing a code generation command. You must only respond with the code you have generated.".to_string()
+        ).into())?;
 
         match (command.message, &state.cli_handler) {
-            (None, Some(handler)) => loop {
+            (None, Some(handler)) if interactive => loop {
                 let prompt = handler
                     .get_message()
                     .context("Failed to retrieve message from user.")?;
 
                 match prompt {
                     ChatAction::Text(text) => {
+                        compact_history(&mut llm_provider, &text, state).await?;
+                        report_prompt_tokens(&llm_provider, &text, state).await;
                         let response = llm_provider
                             .complete_chat(text)
                             .await
                             .context("Failed to retrieve response from the LLM Provider")?;
 
                         output_response(response.as_str(), state);
+                        report_token_usage(&llm_provider, state);
+                        if let Some(session) = &session {
+                            session.save(&llm_provider, state)?;
+                        }
                     }
                     ChatAction::AddFile { path } => {
-                        llm_provider.add_chat_to_context(
-                            handler
-                                .file_handler
-                                .chat_from_file(&path)
-                                .context("Failed to add file to context.")?,
-                        )?;
+                        let attachment = attach(&path).context("Failed to add file to context.")?;
+                        output_files_added(&attachment);
+                        for file in attachment.files {
+                            llm_provider.add_chat_to_context(file)?;
+                        }
+                    }
+                    ChatAction::Context(command) => {
+                        let message = command.run(llm_provider.history_mut())?;
+                        output_context_message(&message);
                     }
                     ChatAction::Clear => {
                         llm_provider.clear_history()?;
@@ -47,19 +69,29 @@ impl Cli {
                 }
             },
             (message, _) => {
-                let prompt = message.context("No message supplied. Use -m to pass a message.")?;
+                let prompt = one_shot_prompt(message, stdin, &mut llm_provider)?;
+                compact_history(&mut llm_provider, &prompt, state).await?;
+                report_prompt_tokens(&llm_provider, &prompt, state).await;
 
-                let mut response = llm_provider
-                    .complete_chat(prompt)
-                    .await
-                    .context("Failed to retrieve response from the LLM Provider")?;
+                if command.no_stream {
+                    let mut response = llm_provider
+                        .complete_chat(prompt)
+                        .await
+                        .context("Failed to retrieve response from the LLM Provider")?;
 
-                if state.quiet {
-                    response = parse_code_response(&response);
-                }
+                    if state.quiet {
+                        response = parse_code_response(&response);
+                    }
 
-                output_response(response.as_str(), state);
+                    output_response(response.as_str(), state);
+                } else {
+                    stream_response(&mut llm_provider, prompt, state, true).await?;
+                }
+                report_token_usage(&llm_provider, state);
 
+                if let Some(session) = &session {
+                    session.save(&llm_provider, state)?;
+                }
                 Ok(())
             }
         }
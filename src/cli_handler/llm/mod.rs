@@ -1,14 +1,25 @@
 mod chat;
 mod code;
+mod stream;
 
+use std::io::{IsTerminal, Read};
+use std::sync::Arc;
+
+use anyhow::Context;
+use futures_util::{FutureExt, future::BoxFuture};
 use termimad::MadSkin;
 
-use super::file_input::FILE_INPUT_TRIGGER;
+use crate::configuration::is_valid_tool_name;
+use crate::provider::{FileUploadData, format_count};
+use crate::tools::{ApprovalRequest, Approver, mcp};
+
+use super::file_input::{Attachment, ContextCommand, FILE_INPUT_TRIGGER};
 use super::{ChatCommand, Cli, CliHandler};
 use super::{CommandState, Provider};
 
 enum ChatAction {
     AddFile { path: String },
+    Context(ContextCommand),
     Text(String),
     Clear,
     End,
@@ -17,7 +28,7 @@ enum ChatAction {
 impl CliHandler {
     fn get_message(&self) -> super::error::Result<ChatAction> {
         let response = inquire::Text::new("Enter message (leave blank to exit):")
-            .with_help_message("Try: '#file:', '/clear'")
+            .with_help_message("Try: '#file:', '/context', '/drop <path>', '/refresh', '/clear'")
             .with_autocomplete(self.file_handler.clone())
             .prompt()
             .map_err(super::error::map_inquire_error)?;
@@ -29,9 +40,11 @@ impl CliHandler {
                     .trim()
                     .to_string(),
             })
+        } else if let Some(command) = ContextCommand::parse(&response) {
+            Ok(ChatAction::Context(command))
         } else if response.trim() == "/clear" {
             Ok(ChatAction::Clear)
-        } else if response == "" {
+        } else if response.is_empty() {
             Ok(ChatAction::End)
         } else {
             Ok(ChatAction::Text(response))
@@ -39,6 +52,173 @@ impl CliHandler {
     }
 }
 
+const STDIN_LABEL: &str = "<stdin>";
+
+/// Read piped input, when stdin is a pipe or a redirected file and `--no-stdin` is not set.
+fn read_piped_stdin(command: &ChatCommand) -> anyhow::Result<Option<String>> {
+    let mut stdin = std::io::stdin();
+    if command.no_stdin || !is_piped(&stdin) {
+        return Ok(None);
+    }
+    let mut input = String::new();
+    stdin
+        .read_to_string(&mut input)
+        .context("Failed to read from stdin.")?;
+    Ok((!input.trim().is_empty()).then_some(input))
+}
+
+/// Whether stdin is a pipe or a file. Other inputs that are not terminals, such as sockets or
+/// `/dev/null` inherited from a parent process, are left unread.
+#[cfg(unix)]
+fn is_piped(stdin: &std::io::Stdin) -> bool {
+    use std::os::{fd::AsFd, unix::fs::FileTypeExt};
+
+    stdin
+        .as_fd()
+        .try_clone_to_owned()
+        .map(std::fs::File::from)
+        .and_then(|file| file.metadata())
+        .is_ok_and(|metadata| metadata.file_type().is_fifo() || metadata.is_file())
+}
+
+#[cfg(not(unix))]
+fn is_piped(stdin: &std::io::Stdin) -> bool {
+    !stdin.is_terminal()
+}
+
+/// Build the prompt for a one shot message. Piped input is attached as context for the `-m`
+/// instruction, or used as the prompt itself when no instruction is given.
+fn one_shot_prompt(
+    message: Option<String>,
+    stdin: Option<String>,
+    provider: &mut Provider,
+) -> anyhow::Result<String> {
+    match (message, stdin) {
+        (Some(message), Some(input)) => {
+            provider.add_chat_to_context(
+                FileUploadData {
+                    text: input,
+                    relative_filepath: STDIN_LABEL.to_string(),
+                }
+                .into(),
+            )?;
+            Ok(message)
+        }
+        (None, Some(input)) => Ok(input),
+        (message, None) => {
+            message.context("No message supplied. Use -m to pass a message or pipe one to stdin.")
+        }
+    }
+}
+
+/// Asks on the terminal before a tool call with side effects runs.
+#[derive(Debug)]
+struct PromptApprover;
+
+impl Approver for PromptApprover {
+    fn approve(&self, request: ApprovalRequest) -> BoxFuture<'_, bool> {
+        let message = format!("Allow {} to {}?", request.tool, request.action);
+        tokio::task::spawn_blocking(move || {
+            inquire::Confirm::new(&message)
+                .with_default(false)
+                .prompt()
+                .unwrap_or(false)
+        })
+        .map(|approved| approved.unwrap_or(false))
+        .boxed()
+    }
+}
+
+/// Let tool calls with side effects ask for approval, when there is a terminal to ask on.
+fn ask_for_tool_approval(provider: &mut Provider) {
+    if std::io::stdin().is_terminal() {
+        provider.set_approver(Arc::new(PromptApprover));
+    }
+}
+
+/// Start the configured MCP servers and offer their tools, saying which servers failed.
+async fn connect_mcp_servers(provider: &mut Provider, state: &CommandState<'_>) {
+    let mut servers = state.config.mcp_servers.iter().collect::<Vec<_>>();
+    servers.sort_by_key(|(name, _)| *name);
+    let connections = futures_util::future::join_all(
+        servers
+            .iter()
+            .map(|(name, options)| mcp::connect(name, options)),
+    )
+    .await;
+    for ((name, _), connection) in servers.into_iter().zip(connections) {
+        match connection {
+            Ok(tools) => {
+                // Server and tool names can hold characters providers reject in a function name.
+                let (tools, invalid): (Vec<_>, Vec<_>) = tools
+                    .into_iter()
+                    .partition(|tool| is_valid_tool_name(tool.name()));
+                for tool in invalid {
+                    eprintln!(
+                        "Skipping tool {} of MCP server {name}, its name is not a valid tool name.",
+                        tool.name()
+                    );
+                }
+                for tool in provider.add_tools(tools) {
+                    eprintln!(
+                        "Skipping tool {tool} of MCP server {name}, another tool has its name."
+                    );
+                }
+            }
+            Err(err) => {
+                tracing::warn!("Failed to connect to MCP server {name}: {:?}", err);
+                eprintln!("Failed to connect to MCP server {name}: {err:#}");
+            }
+        }
+    }
+}
+
+/// Compact the conversation if a request for `prompt` would not fit the context window,
+/// saying so unless `--quiet` is set.
+async fn compact_history(
+    provider: &mut Provider,
+    prompt: &str,
+    state: &CommandState<'_>,
+) -> anyhow::Result<()> {
+    let compaction = provider
+        .compact(prompt)
+        .await
+        .context("Failed to compact the conversation.")?;
+    if let Some(compaction) = compaction
+        && !state.quiet
+    {
+        eprintln!("{}", compaction.summary());
+    }
+    Ok(())
+}
+
+/// With `--verbose`, print how many tokens the request for `prompt` will use before sending it.
+async fn report_prompt_tokens(provider: &Provider, prompt: &str, state: &CommandState<'_>) {
+    if !state.verbose {
+        return;
+    }
+    match provider.count_tokens(prompt.to_string()).await {
+        Ok(count) => {
+            let limit = provider
+                .context_window()
+                .map(|limit| format!(" of {}", format_count(limit)))
+                .unwrap_or_default();
+            eprintln!("Prompt: {count} tokens{limit}");
+        }
+        Err(err) => {
+            tracing::warn!("Failed to count tokens: {:?}", err);
+            eprintln!("Prompt: could not count tokens, {err}");
+        }
+    }
+}
+
+/// With `--verbose`, print the token counts for the latest exchange.
+fn report_token_usage(provider: &Provider, state: &CommandState) {
+    if state.verbose {
+        eprintln!("{}", provider.token_report());
+    }
+}
+
 fn output_response(response: &str, state: &CommandState) {
     if state.quiet {
         print!("{}", response);
@@ -48,7 +228,16 @@ fn output_response(response: &str, state: &CommandState) {
     }
 }
 
-fn output_file_added(path: &str) {
+fn output_context_message(message: &str) {
+    let skin = MadSkin::default();
+    skin.print_text(&format!("---\n```\n{message}\n```\n---"));
+}
+
+fn output_files_added(attachment: &Attachment) {
     let skin = MadSkin::default();
-    skin.print_text(&format!("---\nFile Added: {}\n---", path));
+    let files = attachment
+        .file_paths()
+        .map(|path| format!("* {path}\n"))
+        .collect::<String>();
+    skin.print_text(&format!("---\n{}\n\n{files}---", attachment.summary()));
 }
@@ -0,0 +1,199 @@
+use std::io::Write;
+
+use anyhow::Context;
+use futures_util::StreamExt;
+use termimad::MadSkin;
+
+use crate::provider::{ChatData, StreamChunk, TokenUsage, ToolCallData};
+
+use super::{CommandState, Provider};
+
+const CODE_FENCE: &str = "```";
+
+/// Stream a one shot response to stdout as it arrives, then record the exchange in the
+/// provider's memory like [`Provider::complete_chat`] does.
+///
+/// In quiet mode chunks are written raw. Otherwise markdown is rendered a block at a time,
+/// holding text back until a paragraph or code block is complete so it renders correctly.
+pub(super) async fn stream_response(
+    provider: &mut Provider,
+    prompt: String,
+    state: &CommandState<'_>,
+    code_only: bool,
+) -> anyhow::Result<()> {
+    let mut stream = provider
+        .complete_chat_stream(prompt.clone())
+        .await
+        .context("Failed to retrieve response from the LLM Provider")?;
+
+    let mut output = StreamOutput::new(state.quiet, code_only);
+    let mut usage = TokenUsage::default();
+    let mut turn = Vec::new();
+    while let Some(chunk) = stream.next().await {
+        match chunk.context("Failed to retrieve response from the LLM Provider")? {
+            StreamChunk::Text(text) => output.push(&text)?,
+            StreamChunk::Usage(reported) => usage.merge(reported),
+            StreamChunk::ToolCall(call) => {
+                if !state.quiet {
+                    eprintln!("{}", tool_call_note(&call));
+                }
+                // Text before the call was written alongside it, not as the answer.
+                let text = output.take_reply();
+                if !text.is_empty() {
+                    turn.push(ChatData::model(text).into());
+                }
+                turn.push(call.into());
+            }
+        }
+    }
+    drop(stream);
+
+    let response = output.finish()?;
+    provider.record_usage(usage);
+    provider
+        .update_memory(prompt, turn, response)
+        .context("Failed to update memory.")
+}
+
+struct StreamOutput {
+    quiet: bool,
+    code_only: bool,
+    skin: MadSkin,
+    response: String,
+    /// Byte offset into `response` of the first character not yet written.
+    written: usize,
+    /// Byte offset into `response` where the text after the last tool call starts.
+    reply: usize,
+    started: bool,
+}
+
+impl StreamOutput {
+    fn new(quiet: bool, code_only: bool) -> Self {
+        Self {
+            quiet,
+            code_only,
+            skin: MadSkin::default(),
+            response: String::new(),
+            written: 0,
+            reply: 0,
+            started: false,
+        }
+    }
+
+    fn push(&mut self, chunk: &str) -> anyhow::Result<()> {
+        self.response.push_str(chunk);
+        match (self.quiet, self.code_only) {
+            (true, false) => {
+                self.written = self.response.len();
+                write_raw(chunk)
+            }
+            (true, true) => self.write_code_lines(false),
+            (false, _) => {
+                self.render_markdown(false);
+                Ok(())
+            }
+        }
+    }
+
+    fn finish(mut self) -> anyhow::Result<String> {
+        match (self.quiet, self.code_only) {
+            (true, false) => {}
+            (true, true) => self.write_code_lines(true)?,
+            (false, _) => {
+                self.render_markdown(true);
+                self.skin.print_text("---");
+            }
+        }
+        Ok(self.response.split_off(self.reply))
+    }
+
+    /// Text since the last tool call, leaving it to be written as usual.
+    fn take_reply(&mut self) -> String {
+        let reply = self.response[self.reply..].to_string();
+        self.reply = self.response.len();
+        reply
+    }
+
+    /// Write completed lines, dropping code fences when the response is a fenced code block.
+    fn write_code_lines(&mut self, end: bool) -> anyhow::Result<()> {
+        let fenced = self.response.starts_with(CODE_FENCE);
+        let mut lines = String::new();
+        while let Some(length) = self.next_line(end) {
+            let line = &self.response[self.written..self.written + length];
+            self.written += length;
+            if !(fenced && line.contains(CODE_FENCE)) {
+                lines.push_str(line);
+            }
+        }
+        write_raw(&lines)
+    }
+
+    fn next_line(&self, end: bool) -> Option<usize> {
+        let rest = &self.response[self.written..];
+        match rest.find('\n') {
+            Some(index) => Some(index + 1),
+            None if end && !rest.is_empty() => Some(rest.len()),
+            None => None,
+        }
+    }
+
+    fn render_markdown(&mut self, end: bool) {
+        if !self.started {
+            self.skin.print_text("---");
+            self.started = true;
+        }
+        let rest = &self.response[self.written..];
+        let length = if end {
+            rest.len()
+        } else {
+            complete_blocks_len(rest)
+        };
+        if length == 0 {
+            return;
+        }
+        let block = rest[..length].trim_end_matches('\n');
+        if !block.trim().is_empty() {
+            self.skin.print_text(block);
+        }
+        self.written += length;
+    }
+}
+
+/// Length of the leading text made up of complete markdown blocks: everything up to the last
+/// blank line that is not inside a code block, or up to the end of a closed code block.
+fn complete_blocks_len(text: &str) -> usize {
+    let mut in_fence = false;
+    let mut complete = 0;
+    let mut offset = 0;
+    for line in text.split_inclusive('\n') {
+        offset += line.len();
+        if !line.ends_with('\n') {
+            break;
+        }
+        if line.trim_start().starts_with(CODE_FENCE) {
+            in_fence = !in_fence;
+            if !in_fence {
+                complete = offset;
+            }
+        } else if !in_fence && line.trim().is_empty() {
+            complete = offset;
+        }
+    }
+    complete
+}
+
+fn tool_call_note(call: &ToolCallData) -> String {
+    let outcome = match call.failed {
+        true => "failed",
+        false => "ok",
+    };
+    format!("Called {}({}): {outcome}", call.name, call.arguments)
+}
+
+fn write_raw(text: &str) -> anyhow::Result<()> {
+    let mut stdout = std::io::stdout().lock();
+    stdout
+        .write_all(text.as_bytes())
+        .and_then(|_| stdout.flush())
+        .context("Failed to write response to stdout.")
+}
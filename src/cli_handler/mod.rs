@@ -1,3 +1,5 @@
+use std::path::PathBuf;
+
 use anyhow::Context;
 use clap::{Args, Parser, Subcommand};
 use file_input::FileInputHandler;
@@ -7,9 +9,13 @@ mod error;
 mod file_input;
 mod llm;
 mod ratatui_app;
+mod session;
+
+pub use error::exit_code;
 
 use crate::{
     configuration::Configuration,
+    export::ExportFormat,
     provider::{APIKeyManager, LLMTools, Provider},
 };
 
@@ -27,38 +33,6 @@ impl CliHandler {
             .prompt()
             .map_err(error::map_inquire_error)
     }
-
-    pub fn get_command(&self) -> error::Result<Commands> {
-        const CHAT_COMMAND: &'static str = "Chat";
-        const CHAT_SEARCH_COMMAND: &'static str = "Chat (search)";
-        const CODE_COMMAND: &'static str = "Code";
-        const APIKEY_COMMAND: &'static str = "Set API Key";
-        let options_str: Vec<&str> = vec![
-            CHAT_COMMAND,
-            CHAT_SEARCH_COMMAND,
-            CODE_COMMAND,
-            APIKEY_COMMAND,
-        ];
-        let command = inquire::Select::new("Select option:", options_str)
-            .prompt()
-            .map_err(error::map_inquire_error)?;
-        match command {
-            CHAT_COMMAND => Ok(Commands::Chat(ChatCommand {
-                message: None,
-                search: bool::default(),
-            })),
-            APIKEY_COMMAND => Ok(Commands::SetApiKey(SetApiKeyCommand { key: None })),
-            CHAT_SEARCH_COMMAND => Ok(Commands::Chat(ChatCommand {
-                message: None,
-                search: true,
-            })),
-            CODE_COMMAND => Ok(Commands::Code(ChatCommand {
-                message: None,
-                search: bool::default(),
-            })),
-            _ => Err(error::Error::CommandNotOption(command.to_string())),
-        }
-    }
 }
 
 /// LLM CLI Interface for your LLM needs.
@@ -68,6 +42,10 @@ pub struct Cli {
     #[arg(short, long)]
     quiet: bool,
 
+    /// print token counts for each request to stderr
+    #[arg(short, long)]
+    verbose: bool,
+
     #[command(subcommand)]
     command: Option<Commands>,
 }
@@ -78,15 +56,35 @@ pub struct ChatCommand {
     #[arg(short, long)]
     message: Option<String>,
 
+    /// leave piped stdin unread, e.g. when a script hands down a pipe that never closes
+    #[arg(long)]
+    no_stdin: bool,
+
     #[arg(short, long)]
     search: bool,
+
+    /// wait for the full response before printing it
+    #[arg(long)]
+    no_stream: bool,
+
+    /// save the conversation to a named session, resuming it if it exists
+    #[arg(long)]
+    session: Option<String>,
+
+    /// resume the most recently used session
+    #[arg(short = 'c', long = "continue", conflicts_with = "session")]
+    resume: bool,
+
+    /// enable a local tool the model can call, e.g. `current_time`
+    #[arg(long = "tool")]
+    tools: Vec<String>,
 }
 
 impl ChatCommand {
     pub fn get_tools(&self) -> LLMTools {
         LLMTools {
             search: self.search,
-            ..Default::default()
+            functions: self.tools.iter().cloned().collect(),
         }
     }
 }
@@ -96,12 +94,70 @@ pub struct SetApiKeyCommand {
     /// set api key to value
     #[arg(short, long)]
     key: Option<String>,
+
+    /// provider to set the key for, defaults to the configured provider
+    #[arg(short, long)]
+    provider: Option<String>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ApiKeyCommand {
+    /// list providers and where their api key is found
+    List,
+    /// store an api key in the keyring
+    Set(SetApiKeyCommand),
+    /// delete a provider's api key from the keyring
+    Delete {
+        /// provider to delete the key for
+        provider: String,
+    },
+    /// check the api key is accepted by the provider
+    Verify {
+        /// provider to verify, defaults to the configured provider
+        #[arg(short, long)]
+        provider: Option<String>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum SessionsCommand {
+    /// list saved sessions, most recently used first
+    List,
+    /// print the conversation in a saved session
+    Show {
+        /// session to show
+        name: String,
+    },
+    /// delete a saved session
+    Delete {
+        /// session to delete
+        name: String,
+    },
+    /// export a saved session to markdown, json or html
+    Export {
+        /// session to export
+        name: String,
+
+        #[arg(short, long, value_enum, default_value_t = ExportFormat::Markdown)]
+        format: ExportFormat,
+
+        /// file to write to, defaults to stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
 }
 
 #[derive(Subcommand, Debug)]
 pub enum Commands {
     Chat(ChatCommand),
     Code(ChatCommand),
+    /// manage stored api keys
+    #[command(subcommand)]
+    ApiKey(ApiKeyCommand),
+    /// manage saved chat sessions
+    #[command(subcommand)]
+    Sessions(SessionsCommand),
+    #[command(hide = true)]
     SetApiKey(SetApiKeyCommand),
 }
 
@@ -110,6 +166,11 @@ impl Default for Commands {
         Self::Chat(ChatCommand {
             message: None,
             search: false,
+            no_stream: false,
+            session: None,
+            resume: false,
+            tools: Vec::new(),
+            no_stdin: false,
         })
     }
 }
@@ -125,20 +186,25 @@ impl Cli {
         } else {
             Some(CliHandler::new()?)
         };
-        let state = CommandState::new(cli_handler.as_ref(), config, &api_key_manager, self.quiet);
+        let state = CommandState::new(
+            cli_handler.as_ref(),
+            config,
+            api_key_manager,
+            self.quiet,
+            self.verbose,
+        );
 
         let command = match self.command {
             Some(command) => Some(command),
-            None => match &cli_handler {
-                Some(_handler) => Some(Commands::default()),
-                None => None,
-            },
+            None => cli_handler.as_ref().map(|_handler| Commands::default()),
         }
         .context("No argument given. Use --help for options.")?;
 
         let result = match command {
             Commands::Chat(command) => Cli::handle_chat(command, &state).await,
             Commands::Code(command) => Cli::handle_code(command, &state).await,
+            Commands::ApiKey(command) => Cli::handle_api_key_command(command, &state).await,
+            Commands::Sessions(command) => Cli::handle_sessions_command(command, &state),
             Commands::SetApiKey(command) => Cli::handle_api_key(command, &state),
         };
 
@@ -157,6 +223,7 @@ pub(super) struct CommandState<'a> {
     config: &'a Configuration,
     api_key_manager: &'a APIKeyManager,
     quiet: bool,
+    verbose: bool,
 }
 
 impl<'a> CommandState<'a> {
@@ -165,12 +232,14 @@ impl<'a> CommandState<'a> {
         config: &'a Configuration,
         api_key_manager: &'a APIKeyManager,
         quiet: bool,
+        verbose: bool,
     ) -> Self {
         Self {
             config,
             cli_handler,
             api_key_manager,
             quiet,
+            verbose,
         }
     }
 }
@@ -0,0 +1,96 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use futures_util::{FutureExt, future::BoxFuture};
+use ratatui::{
+    Frame,
+    layout::{Margin, Rect},
+    style::{Color, Style, Stylize},
+    text::Line,
+    widgets::{Block, Clear, Padding, Paragraph, Wrap},
+};
+use tokio::sync::{mpsc::UnboundedSender, oneshot};
+
+use crate::tools::{ApprovalRequest, Approver};
+
+use super::{App, Popover, event_handler::Event, input::Input};
+
+/// Asks in the TUI before a tool call with side effects runs. The generation task waits while
+/// the request is shown.
+#[derive(Debug)]
+pub(super) struct TuiApprover {
+    tx: UnboundedSender<Event>,
+}
+
+impl TuiApprover {
+    pub(super) fn new(tx: UnboundedSender<Event>) -> Self {
+        Self { tx }
+    }
+}
+
+impl Approver for TuiApprover {
+    fn approve(&self, request: ApprovalRequest) -> BoxFuture<'_, bool> {
+        let (reply, answer) = oneshot::channel();
+        let sent = self.tx.send(Event::Approval(request, reply)).is_ok();
+        // A dropped reply, e.g. from closing the TUI, declines.
+        answer
+            .map(move |approved| sent && approved.unwrap_or(false))
+            .boxed()
+    }
+}
+
+impl<'a, 't> App<'a, 't> {
+    pub(super) fn approval_popup(&mut self, area: Rect, frame: &mut Frame) {
+        let Some((request, _)) = &self.approval else {
+            return;
+        };
+        let popover_rect = area.inner(Margin::new(
+            area.width / 6,
+            area.height.saturating_sub(8) / 2,
+        ));
+        frame.render_widget(Clear, popover_rect);
+        let block = Block::bordered()
+            .title("Allow tool call?")
+            .border_style(Style::new().fg(Color::Yellow))
+            .title_bottom(Line::from(vec![
+                " Allow ".into(),
+                "<y>".blue().bold(),
+                " Deny ".into(),
+                "<n> ".blue().bold(),
+            ]))
+            .padding(Padding::horizontal(1));
+
+        let paragraph = Paragraph::new(format!("{} wants to {}.", request.tool, request.action))
+            .block(block)
+            .wrap(Wrap { trim: false });
+        frame.render_widget(paragraph, popover_rect);
+    }
+
+    pub(super) fn request_approval(
+        &mut self,
+        request: ApprovalRequest,
+        reply: oneshot::Sender<bool>,
+    ) {
+        self.approval = Some((request, reply));
+        self.popover = Some(Popover::Approval);
+    }
+
+    pub(super) fn handle_approval_key(&mut self, key_event: KeyEvent) {
+        match (key_event.code, key_event.modifiers) {
+            (KeyCode::Char('y'), KeyModifiers::NONE) => self.answer_approval(true),
+            (KeyCode::Char('n'), KeyModifiers::NONE) | (KeyCode::Esc, _) => {
+                self.answer_approval(false)
+            }
+            _ if matches!(Input::from(key_event), Input::Quit) => {
+                self.answer_approval(false);
+                self.exit();
+            }
+            _ => {}
+        }
+    }
+
+    fn answer_approval(&mut self, approved: bool) {
+        if let Some((_, reply)) = self.approval.take() {
+            let _ = reply.send(approved);
+        }
+        self.popover = None;
+    }
+}
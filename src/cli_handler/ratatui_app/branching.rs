@@ -0,0 +1,122 @@
+use tui_textarea::TextArea;
+
+use crate::provider::{ChatData, ChatHistoryItem, ChatRole};
+
+use super::{App, SelectedZone, state_handling::WindowDirection};
+
+impl<'a, 't> App<'a, 't> {
+    /// Move the selected bubble in the chat history, starting from the latest message.
+    pub(super) fn select_message(&mut self, direction: WindowDirection) {
+        let len = self.provider.get_history().len();
+        if len == 0 {
+            self.selected_message = None;
+            return;
+        }
+        self.selected_message = Some(match (self.selected_message, direction) {
+            (None, _) => len - 1,
+            (Some(index), WindowDirection::Up) => index.saturating_sub(1),
+            (Some(index), WindowDirection::Down) => (index + 1).min(len - 1),
+        });
+        self.scrolling_up = true;
+        self.scroll_to_selection = true;
+    }
+
+    /// Load the selected user message into the prompt. Submitting it starts a new branch from
+    /// that point, keeping the original conversation as an alternative.
+    pub(super) fn edit_selected(&mut self) {
+        let Some(index) = self.selected_message else {
+            return;
+        };
+        if let Some(ChatHistoryItem::Chat(ChatData {
+            role: ChatRole::User,
+            text,
+            ..
+        })) = self.provider.get_history().get(index)
+        {
+            self.textarea = TextArea::from(text.lines());
+            self.textarea.move_cursor(tui_textarea::CursorMove::Bottom);
+            self.textarea.move_cursor(tui_textarea::CursorMove::End);
+            self.editing = Some(index);
+            self.selected_zone = SelectedZone::TextInput;
+        }
+    }
+
+    pub(super) fn cancel_edit(&mut self) {
+        self.editing = None;
+        self.textarea = TextArea::default();
+    }
+
+    /// Generate a new reply to the selected message, or to the message the selected reply
+    /// answered. The previous reply stays available as an alternative branch.
+    pub(super) fn regenerate_selected(&mut self) {
+        let Some(index) = self.selected_message else {
+            return;
+        };
+        let history = self.provider.get_history();
+        let user_index = match history.get(index) {
+            Some(ChatHistoryItem::Chat(chat)) if chat.role == ChatRole::User => Some(index),
+            // Tool calls may sit between a reply and the message it answers.
+            Some(ChatHistoryItem::Chat(ChatData {
+                role: ChatRole::Model,
+                ..
+            }))
+            | Some(ChatHistoryItem::ToolCall(_)) => history.iter().take(index).rposition(|item| {
+                matches!(
+                    item,
+                    ChatHistoryItem::Chat(ChatData {
+                        role: ChatRole::User,
+                        ..
+                    })
+                )
+            }),
+            _ => None,
+        };
+        if let Some(user_index) = user_index {
+            self.regenerate(user_index);
+        }
+    }
+
+    /// Send the latest user message again, e.g. after the provider returned an error.
+    pub(super) fn retry(&mut self) {
+        let last_prompt = self.provider.get_history().iter().rposition(|item| {
+            matches!(
+                item,
+                ChatHistoryItem::Chat(ChatData {
+                    role: ChatRole::User,
+                    ..
+                })
+            )
+        });
+        if let Some(user_index) = last_prompt {
+            self.regenerate(user_index);
+        }
+    }
+
+    /// Replace everything after the user message at `user_index` with a new reply to it.
+    fn regenerate(&mut self, user_index: usize) {
+        let Some(ChatHistoryItem::Chat(ChatData {
+            role: ChatRole::User,
+            text: prompt,
+            ..
+        })) = self.provider.get_history().get(user_index).cloned()
+        else {
+            return;
+        };
+
+        self.provider.history_mut().truncate(user_index + 1);
+        let mut request_provider = self.provider.clone();
+        request_provider.history_mut().truncate(user_index);
+        self.selected_message = Some(user_index + 1);
+        self.start_generation(request_provider, prompt);
+    }
+
+    /// Show the previous or next alternative for the selected message.
+    pub(super) fn switch_selected_branch(&mut self, offset: isize) {
+        let Some(index) = self.selected_message else {
+            return;
+        };
+        if self.provider.history_mut().switch_branch(index, offset) {
+            self.scroll_to_selection = true;
+        }
+    }
+}
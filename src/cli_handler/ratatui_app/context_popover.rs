@@ -0,0 +1,45 @@
+use ratatui::{
+    Frame,
+    layout::{Margin, Rect},
+    style::Stylize,
+    text::Line,
+    widgets::{Block, Clear, Padding, Paragraph},
+};
+
+use crate::cli_handler::file_input::ContextCommand;
+
+use super::{App, Popover};
+
+impl<'a, 't> App<'a, 't> {
+    pub(super) fn context_popup(&mut self, area: Rect, frame: &mut Frame) {
+        let popover_rect = area.inner(Margin::new(5, 5));
+        frame.render_widget(Clear, popover_rect);
+        let block = Block::bordered()
+            .title("Attached files")
+            .title_bottom(Line::from(vec![
+                " Drop ".into(),
+                "/drop <path>".blue().bold(),
+                " Refresh ".into(),
+                "/refresh".blue().bold(),
+                " Close ".into(),
+                "<Esc> ".blue().bold(),
+            ]))
+            .padding(Padding::horizontal(1));
+        frame.render_widget(
+            Paragraph::new(self.context_listing.as_str()).block(block),
+            popover_rect,
+        );
+    }
+
+    /// Run a `/context`, `/drop` or `/refresh` command typed into the prompt.
+    pub(super) fn run_context_command(&mut self, command: ContextCommand) {
+        match command.run(self.provider.history_mut()) {
+            Ok(message) if command == ContextCommand::List => {
+                self.context_listing = message;
+                self.popover = Some(Popover::Context);
+            }
+            Ok(message) => self.status = Some(format!(" {message} ")),
+            Err(err) => self.status = Some(format!(" {err} ")),
+        }
+    }
+}
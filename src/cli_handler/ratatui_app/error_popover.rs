@@ -0,0 +1,54 @@
+use ratatui::{
+    Frame,
+    layout::{Margin, Rect},
+    style::{Color, Style, Stylize},
+    text::Line,
+    widgets::{Block, Clear, Padding, Paragraph, Wrap},
+};
+
+use super::{App, Popover};
+
+impl<'a, 't> App<'a, 't> {
+    pub(super) fn error_popup(&mut self, area: Rect, frame: &mut Frame) {
+        let Some(error) = &self.error else {
+            return;
+        };
+        let popover_rect = area.inner(Margin::new(
+            area.width / 6,
+            area.height.saturating_sub(12) / 2,
+        ));
+        frame.render_widget(Clear, popover_rect);
+        let block = Block::bordered()
+            .title("Error")
+            .border_style(Style::new().fg(Color::Red))
+            .title_bottom(Line::from(vec![
+                " Retry ".into(),
+                "<C-R>".blue().bold(),
+                " Close ".into(),
+                "<Esc> ".blue().bold(),
+            ]))
+            .padding(Padding::horizontal(1));
+
+        let paragraph = Paragraph::new(error.as_str())
+            .block(block)
+            .wrap(Wrap { trim: false });
+        frame.render_widget(paragraph, popover_rect);
+    }
+
+    /// Stop the current generation and tell the user why, both in a popover and as a bubble at
+    /// the end of the conversation that stays until the next response.
+    pub(super) fn show_error(&mut self, err: anyhow::Error) {
+        tracing::error!("Error occurred: {:?}", err);
+        if let Some(generation) = self.generation.take() {
+            generation.abort();
+        }
+        if let Some(index) = self.last_added_index.take() {
+            self.provider.history_mut().mark_truncated(index);
+        }
+        self.error = Some(format!("{err:#}"));
+        self.popover = Some(Popover::Error);
+        if !self.scrolling_up {
+            self.scrollview_state.scroll_to_bottom();
+        }
+    }
+}
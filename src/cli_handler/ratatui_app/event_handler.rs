@@ -4,6 +4,9 @@ use futures_util::StreamExt;
 use tokio::sync::mpsc::UnboundedSender;
 use tokio::{sync::mpsc, task::JoinHandle};
 
+use crate::provider::{Compaction, TokenUsage, ToolCallData};
+use crate::tools::ApprovalRequest;
+
 #[derive(Debug)]
 pub struct EventHandler {
     tx: tokio::sync::mpsc::UnboundedSender<Event>,
@@ -16,6 +19,11 @@ pub enum Event {
     Tick,
     Key(crossterm::event::KeyEvent),
     LlmResponse(LlmResponse),
+    /// A tool call waiting for the user, answered on the sender.
+    Approval(ApprovalRequest, tokio::sync::oneshot::Sender<bool>),
+    /// Reading the terminal failed, so key presses, including quitting, may no longer arrive.
+    TerminalError(anyhow::Error),
+    /// A response could not be generated.
     Error(anyhow::Error),
 }
 
@@ -23,6 +31,9 @@ pub enum Event {
 pub enum LlmResponse {
     Finished,
     Chunk(String),
+    Usage(TokenUsage),
+    ToolCall(ToolCallData),
+    Compacted(Compaction),
 }
 
 impl EventHandler {
@@ -42,17 +53,13 @@ impl EventHandler {
                   maybe_event = crossterm_event => {
                     match maybe_event {
                       Some(Ok(evt)) => {
-                        match evt {
-                          crossterm::event::Event::Key(key) => {
-                            if key.kind == crossterm::event::KeyEventKind::Press {
-                              _tx.send(Event::Key(key)).unwrap();
-                            }
-                          },
-                          _ => {},
-                        }
+                        if let crossterm::event::Event::Key(key) = evt
+                          && key.kind == crossterm::event::KeyEventKind::Press {
+                            _tx.send(Event::Key(key)).unwrap();
+                          }
                       }
                       Some(Err(err)) => {
-                        _tx.send(Event::Error(anyhow!("Error reading terminal event. {}", err))).unwrap();
+                        _tx.send(Event::TerminalError(anyhow!("Error reading terminal event. {}", err))).unwrap();
                       }
                       None => {},
                     }
@@ -79,3 +86,11 @@ impl EventHandler {
         self.tx.clone()
     }
 }
+
+impl Drop for EventHandler {
+    fn drop(&mut self) {
+        if let Some(task) = self.task.take() {
+            task.abort();
+        }
+    }
+}
@@ -0,0 +1,55 @@
+use anyhow::Context;
+use ratatui::{
+    Frame,
+    layout::{Margin, Rect},
+    style::{Color, Modifier, Style},
+    widgets::{Block, Clear, HighlightSpacing, List, ListItem},
+};
+use strum::IntoEnumIterator;
+
+use crate::export::{self, ExportFormat};
+
+use super::App;
+
+const SELECTED_STYLE: Style = Style::new().bg(Color::Blue).add_modifier(Modifier::BOLD);
+const EXPORT_TITLE: &str = "llcli_rs conversation";
+
+impl<'a, 't> App<'a, 't> {
+    pub(super) fn export_popup(&mut self, area: Rect, frame: &mut Frame) {
+        let popover_rect = area.inner(Margin::new(5, 5));
+        frame.render_widget(Clear, popover_rect);
+        let block = Block::bordered().title("Export conversation to current directory");
+
+        let items: Vec<ListItem> = ExportFormat::iter()
+            .map(|format| ListItem::new(format!(" {} (.{})", format.display(), format.extension())))
+            .collect();
+
+        let list = List::new(items)
+            .block(block)
+            .highlight_style(SELECTED_STYLE)
+            .highlight_symbol(">")
+            .highlight_spacing(HighlightSpacing::Always);
+
+        frame.render_stateful_widget(list, popover_rect, &mut self.export_options_state);
+    }
+
+    /// Write the conversation in the selected format, reporting the outcome in the status line.
+    pub(super) fn export_selected(&mut self) -> anyhow::Result<()> {
+        let Some(selected) = self.export_options_state.selected() else {
+            return Ok(());
+        };
+        let format =
+            ExportFormat::from_repr(selected).context("Failed to get export format from index.")?;
+        let path = export::default_file_name(format);
+        let result = export::export(self.provider.memory(), EXPORT_TITLE, format)
+            .and_then(|exported| std::fs::write(&path, exported).map_err(anyhow::Error::from));
+        self.status = Some(match result {
+            Ok(()) => format!(" Exported to {path} "),
+            Err(err) => {
+                tracing::error!("Export failed: {:?}", err);
+                format!(" Export failed: {err} ")
+            }
+        });
+        Ok(())
+    }
+}
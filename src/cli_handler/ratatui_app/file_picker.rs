@@ -0,0 +1,211 @@
+use std::path::Path;
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use fuzzy_matcher::{FuzzyMatcher, skim::SkimMatcherV2};
+use ratatui::{
+    Frame,
+    layout::{Constraint, Layout, Margin, Rect},
+    style::{Color, Modifier, Style, Stylize},
+    text::Line,
+    widgets::{Block, Clear, HighlightSpacing, List, ListItem, ListState, Paragraph},
+};
+
+use crate::cli_handler::file_input::{self, Attachment};
+
+use super::{App, Popover, input::Input};
+
+const SELECTED_STYLE: Style = Style::new().bg(Color::Blue).add_modifier(Modifier::BOLD);
+/// Stop walking large trees so opening the picker stays instant.
+const MAX_PICKER_FILES: usize = 20_000;
+const MENTION_TRIGGER: char = '@';
+
+/// Fuzzy finder over the files and directories below the current directory, skipping anything
+/// git ignores. Directories are listed with a trailing separator.
+#[derive(Debug, Default)]
+pub(super) struct FilePicker {
+    files: Vec<String>,
+    query: String,
+    matches: Vec<String>,
+    state: ListState,
+}
+
+impl FilePicker {
+    fn open() -> Self {
+        let files = ignore::WalkBuilder::new(".")
+            .build()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.depth() > 0)
+            .filter_map(|entry| {
+                let path = entry.path().strip_prefix(".").ok()?.to_str()?;
+                match entry.file_type()?.is_dir() {
+                    true => Some(format!("{path}{}", std::path::MAIN_SEPARATOR)),
+                    false => Some(path.to_string()),
+                }
+            })
+            .take(MAX_PICKER_FILES)
+            .collect();
+        let mut picker = Self {
+            files,
+            ..Default::default()
+        };
+        picker.update_matches();
+        picker
+    }
+
+    fn update_matches(&mut self) {
+        let matcher = SkimMatcherV2::default();
+        let mut scored = self
+            .files
+            .iter()
+            .filter_map(|file| Some((matcher.fuzzy_match(file, &self.query)?, file)))
+            .collect::<Vec<_>>();
+        // Prefer better matches, then shorter paths, as those are usually what was meant.
+        scored.sort_by_key(|(score, file)| (std::cmp::Reverse(*score), file.len()));
+        self.matches = scored.into_iter().map(|(_, file)| file.clone()).collect();
+        self.state.select((!self.matches.is_empty()).then_some(0));
+    }
+
+    fn selected(&self) -> Option<&str> {
+        self.matches.get(self.state.selected()?).map(String::as_str)
+    }
+}
+
+impl<'a, 't> App<'a, 't> {
+    /// Open the picker when `@` starts a new word, so addresses like `me@example.com` can still
+    /// be typed. Returns whether the key was used.
+    pub(super) fn open_file_picker(&mut self, key_event: KeyEvent) -> bool {
+        if key_event.code != KeyCode::Char(MENTION_TRIGGER)
+            || key_event.modifiers.contains(KeyModifiers::CONTROL)
+        {
+            return false;
+        }
+        let (row, col) = self.textarea.cursor();
+        let starts_word = col == 0
+            || self.textarea.lines()[row]
+                .chars()
+                .nth(col - 1)
+                .is_some_and(char::is_whitespace);
+        if !starts_word {
+            return false;
+        }
+        self.file_picker = FilePicker::open();
+        self.popover = Some(Popover::FilePicker);
+        true
+    }
+
+    pub(super) fn handle_file_picker_key(&mut self, key_event: KeyEvent) {
+        let picker = &mut self.file_picker;
+        match (key_event.code, key_event.modifiers) {
+            (KeyCode::Up, _) => picker.state.select_previous(),
+            (KeyCode::Down, _) => picker.state.select_next(),
+            (KeyCode::Enter, _) | (KeyCode::Tab, _) => {
+                let mention = picker
+                    .selected()
+                    .map(|file| format!("{MENTION_TRIGGER}{file} "));
+                if let Some(mention) = mention {
+                    self.textarea.insert_str(mention);
+                    self.popover = None;
+                }
+            }
+            (KeyCode::Esc, _) => {
+                // Keep the `@` that was typed, it was probably meant literally.
+                self.textarea.insert_char(MENTION_TRIGGER);
+                self.popover = None;
+            }
+            (KeyCode::Backspace, _) => {
+                if picker.query.pop().is_none() {
+                    self.popover = None;
+                } else {
+                    picker.update_matches();
+                }
+            }
+            (KeyCode::Char(c), KeyModifiers::NONE | KeyModifiers::SHIFT) => {
+                picker.query.push(c);
+                picker.update_matches();
+            }
+            _ => {
+                if Input::from(key_event) == Input::Quit {
+                    self.exit();
+                }
+            }
+        }
+    }
+
+    pub(super) fn file_picker_popup(&mut self, area: Rect, frame: &mut Frame) {
+        let picker = &mut self.file_picker;
+        let popover_rect = area.inner(Margin::new(5, 5));
+        frame.render_widget(Clear, popover_rect);
+        let block = Block::bordered()
+            .title("Attach file")
+            .title_bottom(Line::from(vec![
+                " Attach ".into(),
+                "<Enter>".blue().bold(),
+                " Cancel ".into(),
+                "<Esc> ".blue().bold(),
+            ]));
+        let inner = block.inner(popover_rect);
+        frame.render_widget(block, popover_rect);
+
+        let [query_area, list_area] =
+            Layout::vertical([Constraint::Length(1), Constraint::Fill(1)]).areas(inner);
+        frame.render_widget(
+            Paragraph::new(Line::from(vec![
+                format!("{MENTION_TRIGGER}").green(),
+                picker.query.as_str().into(),
+            ])),
+            query_area,
+        );
+
+        let items = picker
+            .matches
+            .iter()
+            .map(|file| ListItem::new(format!(" {file}")))
+            .collect::<Vec<_>>();
+        let list = List::new(items)
+            .highlight_style(SELECTED_STYLE)
+            .highlight_symbol(">")
+            .highlight_spacing(HighlightSpacing::Always);
+        frame.render_stateful_widget(list, list_area, &mut picker.state);
+    }
+}
+
+/// Files referenced as `@path`, `@dir/` or `@glob` in a prompt, in the order they are first
+/// mentioned. Mentions that match nothing are left as plain text.
+pub(super) fn mentioned_files(prompt: &str) -> anyhow::Result<Vec<Attachment>> {
+    let mut patterns: Vec<&str> = Vec::new();
+    for word in prompt.split_whitespace() {
+        let Some(mention) = word.strip_prefix(MENTION_TRIGGER) else {
+            continue;
+        };
+        // Allow punctuation after a mention, as in "compare @a.rs, @b.rs."
+        let candidates = [
+            mention,
+            mention.trim_end_matches([',', '.', ';', ':', ')', '?', '!']),
+        ];
+        let pattern = candidates
+            .iter()
+            .find(|pattern| !pattern.is_empty() && Path::new(pattern).exists())
+            .or_else(|| {
+                candidates
+                    .iter()
+                    .rev()
+                    .find(|pattern| file_input::is_glob(pattern))
+            });
+        if let Some(pattern) = pattern
+            && !patterns.contains(pattern)
+        {
+            patterns.push(pattern);
+        }
+    }
+
+    let mut attachments = Vec::new();
+    for pattern in patterns {
+        match file_input::attach(pattern) {
+            Ok(attachment) => attachments.push(attachment),
+            // Globs that match nothing, such as `@*emphasis*`, are text.
+            Err(_) if !Path::new(pattern).exists() => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(attachments)
+}
@@ -1,7 +1,5 @@
 use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
 
-use super::{Popover, SelectedZone};
-
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Input {
     ScrollUp,
@@ -13,10 +11,17 @@ pub enum Input {
     Submit,
     Enter,
     ToggleLlmOptions,
+    Export,
+    Edit,
+    Regenerate,
+    BranchPrevious,
+    BranchNext,
+    Stop,
+    Retry,
     None,
 }
 
-impl From<(KeyEvent)> for Input {
+impl From<KeyEvent> for Input {
     fn from(value: KeyEvent) -> Self {
         let key_event = value;
 
@@ -32,8 +37,17 @@ impl From<(KeyEvent)> for Input {
             (KeyCode::Char('k'), KeyModifiers::NONE) => Input::ScrollUp,
             (KeyCode::Char('j'), KeyModifiers::NONE) => Input::ScrollDown,
             (KeyCode::Enter, KeyModifiers::NONE) => Input::Enter,
+            (KeyCode::Char('e'), KeyModifiers::NONE) => Input::Edit,
+            (KeyCode::Char('r'), KeyModifiers::NONE) => Input::Regenerate,
+            (KeyCode::Left, KeyModifiers::NONE) => Input::BranchPrevious,
+            (KeyCode::Right, KeyModifiers::NONE) => Input::BranchNext,
+            (KeyCode::Char('h'), KeyModifiers::NONE) => Input::BranchPrevious,
+            (KeyCode::Char('l'), KeyModifiers::NONE) => Input::BranchNext,
             (KeyCode::Char('s'), KeyModifiers::CONTROL) => Input::Submit,
             (KeyCode::Tab, KeyModifiers::NONE) => Input::ToggleLlmOptions,
+            (KeyCode::Char('e'), KeyModifiers::CONTROL) => Input::Export,
+            (KeyCode::Char('x'), KeyModifiers::CONTROL) => Input::Stop,
+            (KeyCode::Char('r'), KeyModifiers::CONTROL) => Input::Retry,
             (KeyCode::Esc, _) => Input::Back,
             _ => Input::None,
         }
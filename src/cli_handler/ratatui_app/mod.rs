@@ -1,3 +1,5 @@
+use std::sync::Arc;
+
 use ratatui::{
     layout::Margin,
     prelude::StatefulWidget,
@@ -6,22 +8,31 @@ use ratatui::{
     widgets::{ListState, Wrap},
 };
 
+use approval_popover::TuiApprover;
 use event_handler::EventHandler;
 use ratatui::{
     DefaultTerminal, Frame,
     buffer::Buffer,
-    layout::{Constraint, Layout, Rect, Size},
+    layout::{Constraint, Layout, Position, Rect, Size},
     style::{Style, Stylize},
     symbols::border,
     text::Line,
     widgets::{Block, Padding, Paragraph, Widget},
 };
+use tokio::{sync::oneshot, task::JoinHandle};
 use tui_scrollview::ScrollViewState;
 use tui_textarea::TextArea;
 
-use crate::provider::{ChatHistoryItem, ChatRole, Provider};
+use crate::provider::{ChatHistoryItem, ChatRole, Provider, ToolCallData};
+use crate::tools::ApprovalRequest;
 
+mod approval_popover;
+mod branching;
+mod context_popover;
+mod error_popover;
 mod event_handler;
+mod export_popover;
+mod file_picker;
 mod input;
 mod state_handling;
 mod tool_list_popover;
@@ -37,7 +48,22 @@ pub struct App<'a, 't> {
     selected_zone: SelectedZone,
     popover: Option<Popover>,
     llm_tool_options_state: ListState,
-    generating: bool,
+    export_options_state: ListState,
+    file_picker: file_picker::FilePicker,
+    /// Output of the last `/context` command.
+    context_listing: String,
+    status: Option<String>,
+    /// Bubble selected in the chat history, as an index into the active branch.
+    selected_message: Option<usize>,
+    scroll_to_selection: bool,
+    /// Index of the user message being edited in the prompt.
+    editing: Option<usize>,
+    /// Last error from the provider, shown after the conversation until the next response.
+    error: Option<String>,
+    /// Task streaming the current response, if one is being generated.
+    generation: Option<JoinHandle<()>>,
+    /// Tool call waiting for the user to allow or deny it.
+    approval: Option<(ApprovalRequest, oneshot::Sender<bool>)>,
     scrolling_up: bool,
 }
 
@@ -50,28 +76,47 @@ enum SelectedZone {
 #[derive(Debug, Clone, Copy)]
 enum Popover {
     LlmToolList,
+    Export,
+    Error,
+    FilePicker,
+    Context,
+    Approval,
 }
 
 impl<'a, 't> App<'a, 't> {
     pub fn new(provider: &'a mut Provider) -> Self {
+        let event_handler = EventHandler::new();
+        provider.set_approver(Arc::new(TuiApprover::new(event_handler.get_sender())));
         Self {
             provider,
-            event_handler: EventHandler::new(),
+            event_handler,
             exit: false,
             textarea: Self::create_chat_input(),
             selected_zone: SelectedZone::TextInput,
             scrollview_state: ScrollViewState::default(),
-            generating: false,
+            generation: None,
+            approval: None,
+            error: None,
             last_added_index: None,
             popover: None,
             llm_tool_options_state: ListState::default().with_selected(Some(0)),
+            export_options_state: ListState::default().with_selected(Some(0)),
+            file_picker: file_picker::FilePicker::default(),
+            context_listing: String::new(),
+            status: None,
+            selected_message: None,
+            scroll_to_selection: false,
+            editing: None,
             scrolling_up: false,
         }
     }
 
     fn create_chat_input() -> TextArea<'t> {
-        let textarea = TextArea::default();
-        textarea
+        TextArea::default()
+    }
+
+    fn generating(&self) -> bool {
+        self.generation.is_some()
     }
 
     pub async fn run(&mut self, terminal: &mut DefaultTerminal) -> anyhow::Result<()> {
@@ -97,20 +142,51 @@ impl<'a, 't> App<'a, 't> {
 
         let buf = frame.buffer_mut();
 
-        let instructions = Line::from(vec![
-            Span::from(" Quit "),
-            Span::from("<Ctrl-c>").fg(Color::Blue),
-            Span::from(" Scroll Up "),
-            Span::from("<Up Arrow>").fg(Color::Blue),
-            Span::from(" Scroll Down "),
-            Span::from("<Down Arrow>").fg(Color::Blue),
-        ]);
-
         let scrollview_selected = self.selected_zone == SelectedZone::ChatHistory;
-        Self::build_block(scrollview_selected)
+        let instructions = if scrollview_selected {
+            Line::from(vec![
+                Span::from(" Select "),
+                Span::from("<Up/Down>").fg(Color::Blue),
+                Span::from(" Edit "),
+                Span::from("<e>").fg(Color::Blue),
+                Span::from(" Regenerate "),
+                Span::from("<r>").fg(Color::Blue),
+                Span::from(" Branch "),
+                Span::from("<Left/Right>").fg(Color::Blue),
+                Span::from(" Export "),
+                Span::from("<C-E>").fg(Color::Blue),
+            ])
+        } else {
+            Line::from(vec![
+                Span::from(" Quit "),
+                Span::from("<Ctrl-c>").fg(Color::Blue),
+                Span::from(" History "),
+                Span::from("<C-K>").fg(Color::Blue),
+                Span::from(" Export "),
+                Span::from("<C-E>").fg(Color::Blue),
+            ])
+        };
+
+        let mut history_block = Self::build_block(scrollview_selected)
             .title("History")
-            .title_bottom(instructions)
-            .render(layout[0], buf);
+            .title_bottom(instructions);
+        if let Some(status) = &self.status {
+            history_block = history_block.title(Line::from(status.clone()).right_aligned());
+        }
+        history_block.render(layout[0], buf);
+        if self.scroll_to_selection {
+            self.scroll_to_selection = false;
+            if let Some(selected) = self.selected_message {
+                let offset = self
+                    .provider
+                    .get_history()
+                    .iter()
+                    .take(selected)
+                    .map(|item| Self::item_height(item, scrollview_area.width))
+                    .sum();
+                self.scrollview_state.set_offset(Position::new(0, offset));
+            }
+        }
         scrollview.render(scrollview_area, buf, &mut self.scrollview_state);
 
         self.draw_text_area_widget(buf, layout[1]);
@@ -118,6 +194,11 @@ impl<'a, 't> App<'a, 't> {
         if let Some(popover) = self.popover {
             match popover {
                 Popover::LlmToolList => self.llm_options_popup(area, frame),
+                Popover::Export => self.export_popup(area, frame),
+                Popover::Error => self.error_popup(area, frame),
+                Popover::FilePicker => self.file_picker_popup(area, frame),
+                Popover::Context => self.context_popup(area, frame),
+                Popover::Approval => self.approval_popup(area, frame),
             }
         }
     }
@@ -126,14 +207,27 @@ impl<'a, 't> App<'a, 't> {
         self.provider
             .get_history()
             .iter()
-            .map(|item| match item {
-                ChatHistoryItem::FileUpload(_) => 3,
-                ChatHistoryItem::Chat(chat) => {
-                    Self::count_wrapped_lines(&chat.text, term_width) + 2
-                }
-            })
+            .map(|item| Self::item_height(item, term_width))
             .reduce(|acc, item| acc + item)
             .unwrap_or(0)
+            + self.error_height(term_width)
+    }
+
+    fn error_height(&self, term_width: u16) -> u16 {
+        self.error
+            .as_ref()
+            .map(|error| Self::count_wrapped_lines(error, term_width) + 2)
+            .unwrap_or(0)
+    }
+
+    fn item_height(item: &ChatHistoryItem, term_width: u16) -> u16 {
+        match item {
+            ChatHistoryItem::FileUpload(_) => 3,
+            ChatHistoryItem::Chat(chat) => Self::count_wrapped_lines(&chat.text, term_width) + 2,
+            ChatHistoryItem::ToolCall(call) => {
+                Self::count_wrapped_lines(&tool_call_text(call), term_width) + 2
+            }
+        }
     }
 
     fn count_wrapped_lines(text: &str, width: u16) -> u16 {
@@ -157,13 +251,28 @@ impl<'a, 't> App<'a, 't> {
 
 impl<'a, 't> App<'a, 't> {
     fn draw_text_area_widget(&mut self, buf: &mut Buffer, area: Rect) {
-        let instructions = Line::from(vec![" Submit ".into(), "<C-S>".blue().bold()]);
-        self.textarea.set_block(
-            Self::build_block(self.selected_zone == SelectedZone::TextInput)
-                .title("Prompt")
-                .padding(Padding::new(2, 0, 0, 0))
-                .title_bottom(instructions),
-        );
+        let mut instructions = if self.generating() {
+            vec![" Stop ".into(), "<C-X>".blue().bold()]
+        } else {
+            vec![" Submit ".into(), "<C-S>".blue().bold()]
+        };
+        if self.editing.is_some() {
+            instructions.extend([" Cancel edit ".into(), "<Esc>".blue().bold()]);
+        }
+        let title = if self.editing.is_some() {
+            "Prompt (editing message)"
+        } else {
+            "Prompt"
+        };
+        let mut block = Self::build_block(self.selected_zone == SelectedZone::TextInput)
+            .title(title)
+            .padding(Padding::new(2, 0, 0, 0))
+            .title_bottom(Line::from(instructions));
+        if !self.provider.get_history().is_empty() {
+            let tokens = format!(" {} ", self.provider.token_report());
+            block = block.title(Line::from(tokens).right_aligned());
+        }
+        self.textarea.set_block(block);
         self.textarea
             .set_placeholder_text("Enter prompt or @path-to-file");
         self.textarea.render(area, buf);
@@ -193,34 +302,67 @@ impl<'a, 't> App<'a, 't> {
 
     fn render_widgets_into_scrollview(&self, buf: &mut Buffer) {
         let area = buf.area;
-        let constraints = self.provider.get_history().iter().map(|chat| match chat {
-            ChatHistoryItem::Chat(chat) => {
-                Self::count_wrapped_lines(&chat.text, buf.area.width) + 2
-            }
-            ChatHistoryItem::FileUpload(_) => 3,
-        });
+        let constraints = self
+            .provider
+            .get_history()
+            .iter()
+            .map(|chat| Self::item_height(chat, buf.area.width))
+            .chain([self.error_height(buf.area.width)]);
         let layouts = Layout::vertical(constraints).split(area);
 
         for (index, chat) in self.provider.get_history().iter().enumerate() {
-            self.bubble(chat).render(layouts[index], buf);
+            self.bubble(index, chat).render(layouts[index], buf);
+        }
+        if let Some(error) = &self.error {
+            Paragraph::new(error.as_str())
+                .block(
+                    Block::bordered()
+                        .border_style(Style::new().red())
+                        .title("Error")
+                        .title_style(Style::new().red().add_modifier(Modifier::BOLD))
+                        .title_bottom(Line::from(vec![" Retry ".into(), "<C-R> ".blue().bold()])),
+                )
+                .wrap(Wrap { trim: true })
+                .render(layouts[layouts.len() - 1], buf);
         }
     }
 
-    fn bubble(&self, chat: &ChatHistoryItem) -> impl Widget {
-        let block = Block::bordered();
+    fn bubble(&self, index: usize, chat: &ChatHistoryItem) -> impl Widget {
+        let selected =
+            self.selected_zone == SelectedZone::ChatHistory && self.selected_message == Some(index);
+        let block = if selected {
+            Block::bordered()
+                .border_set(border::THICK)
+                .border_style(Style::new().light_blue())
+        } else {
+            Block::bordered()
+        };
 
         match chat {
             ChatHistoryItem::Chat(message) => {
                 let text = tui_markdown::from_str(&message.text);
-                let title_color = if message.role == ChatRole::User {
-                    Color::Gray
-                } else {
-                    Color::Blue
+                let title_color = match message.role {
+                    ChatRole::User => Color::Gray,
+                    ChatRole::Context => Color::Yellow,
+                    ChatRole::Model | ChatRole::System => Color::Blue,
                 };
+                let mut title = message.role.display().to_string();
+                if let Some(position) = self
+                    .provider
+                    .memory()
+                    .history
+                    .branch_position(index)
+                    .filter(|position| position.count > 1)
+                {
+                    title.push_str(&format!(" ({}/{})", position.index + 1, position.count));
+                }
+                if message.truncated {
+                    title.push_str(" (stopped)");
+                }
                 Paragraph::new(text)
                     .block(
                         block
-                            .title(message.role.display())
+                            .title(title)
                             .title_style(Style::new().fg(title_color).add_modifier(Modifier::BOLD)),
                     )
                     .wrap(Wrap { trim: true })
@@ -228,6 +370,34 @@ impl<'a, 't> App<'a, 't> {
             ChatHistoryItem::FileUpload(file) => {
                 Paragraph::new(file.relative_filepath.clone()).block(block.title("File upload"))
             }
+            ChatHistoryItem::ToolCall(call) => {
+                let title = match call.failed {
+                    true => "Tool call (failed)",
+                    false => "Tool call",
+                };
+                Paragraph::new(tool_call_text(call))
+                    .block(block.title(title).title_style(Style::new().dark_gray()))
+                    .wrap(Wrap { trim: true })
+            }
         }
     }
 }
+
+/// Lines of output shown for a tool call, the model sees all of it.
+const TOOL_OUTPUT_PREVIEW_LINES: usize = 3;
+
+/// The call with the start of its output, as shown in the history.
+fn tool_call_text(call: &ToolCallData) -> String {
+    let mut text = format!("{}({})", call.name, call.arguments);
+    let lines = call.output.lines().collect::<Vec<_>>();
+    for line in lines.iter().take(TOOL_OUTPUT_PREVIEW_LINES) {
+        text.push_str(&format!("\n{line}"));
+    }
+    if lines.len() > TOOL_OUTPUT_PREVIEW_LINES {
+        text.push_str(&format!(
+            "\n… {} more lines",
+            lines.len() - TOOL_OUTPUT_PREVIEW_LINES
+        ));
+    }
+    text
+}
@@ -1,21 +1,21 @@
 use anyhow::Context;
-use crossterm::event::{KeyCode, KeyModifiers};
 use futures_util::StreamExt;
 use tracing::trace;
 use tui_textarea::TextArea;
 
 use crate::{
-    cli_handler::ratatui_app::tool_list_popover::LlmToolEnum,
-    provider::{ChatData, ChatHistoryItem, Provider},
+    cli_handler::file_input::ContextCommand,
+    provider::{ChatData, ChatHistoryItem, Provider, StreamChunk},
 };
 
 use super::{
     App, Popover, SelectedZone,
     event_handler::{Event, LlmResponse},
+    file_picker,
     input::Input,
 };
 
-enum WindowDirection {
+pub(super) enum WindowDirection {
     Up,
     Down,
 }
@@ -29,6 +29,8 @@ impl<'a, 't> App<'a, 't> {
                     self.handle_key_event(key_event)?;
                 }
             }
+            // Chunks already queued when a generation was stopped are dropped.
+            Event::LlmResponse(LlmResponse::Chunk(_)) if !self.generating() => {}
             Event::LlmResponse(LlmResponse::Chunk(chunk)) => {
                 tracing::trace!("Handling LLM Response Chunk: {:?}", chunk);
                 if let Some(index) = self.last_added_index {
@@ -42,18 +44,36 @@ impl<'a, 't> App<'a, 't> {
                     self.scrollview_state.scroll_to_bottom();
                 }
             }
+            Event::LlmResponse(LlmResponse::Usage(usage)) if self.generating() => {
+                self.provider.record_usage(usage);
+            }
+            Event::LlmResponse(LlmResponse::ToolCall(call)) if self.generating() => {
+                self.provider.add_chat_to_context(call.into())?;
+                // Text after the call is a new reply.
+                self.last_added_index = None;
+                if !self.scrolling_up {
+                    self.scrollview_state.scroll_to_bottom();
+                }
+            }
+            Event::LlmResponse(LlmResponse::Compacted(compaction)) if self.generating() => {
+                compaction.apply(self.provider.history_mut());
+                self.selected_message = None;
+                self.status = Some(format!(" {} ", compaction.summary()));
+            }
             Event::LlmResponse(LlmResponse::Finished) => {
                 tracing::trace!("Handling LLM Response Finished");
-                self.generating = false;
+                self.generation = None;
                 self.last_added_index = None;
             }
-            Event::Error(err) => {
+            Event::Approval(request, reply) if self.generating() => {
+                self.request_approval(request, reply);
+            }
+            Event::TerminalError(err) => {
                 tracing::error!("Error occurred: {:?}", err);
-                if err.root_cause().is::<std::io::Error>() {
-                    return Err(err).context("Critical Event Handling Error. Exiting as keyboard inputs could fail to exit program.");
-                }
-                self.generating = false;
-                self.last_added_index = None;
+                return Err(err).context("Critical Event Handling Error. Exiting as keyboard inputs could fail to exit program.");
+            }
+            Event::Error(err) => {
+                self.show_error(err);
             }
             _ => {}
         }
@@ -65,7 +85,15 @@ impl<'a, 't> App<'a, 't> {
         let input = Input::from(key_event);
         trace!("Decoded input: {:?}", input);
 
-        // Popover first.
+        // Popover first. The file picker takes typed characters for its query.
+        if let Some(Popover::FilePicker) = self.popover {
+            self.handle_file_picker_key(key_event);
+            return Ok(());
+        }
+        if let Some(Popover::Approval) = self.popover {
+            self.handle_approval_key(key_event);
+            return Ok(());
+        }
         if let Some(popover) = self.popover {
             let handled = match input {
                 Input::Back => {
@@ -93,10 +121,12 @@ impl<'a, 't> App<'a, 't> {
                     }
                     Input::Enter => {
                         if let Some(selected) = self.llm_tool_options_state.selected() {
-                            self.provider.flags_mut().toggle(
-                                LlmToolEnum::from_repr(selected)
-                                    .context("Failed to get LLM Tool Setting from index.")?,
-                            );
+                            let item = self
+                                .llm_tool_options()
+                                .into_iter()
+                                .nth(selected)
+                                .context("Failed to get LLM Tool Setting from index.")?;
+                            self.provider.flags_mut().toggle(item);
                         }
                         true
                     }
@@ -107,6 +137,39 @@ impl<'a, 't> App<'a, 't> {
                     }
                     _ => false,
                 },
+                Popover::Error => match input {
+                    Input::Enter => {
+                        self.popover = None;
+                        true
+                    }
+                    Input::Retry => {
+                        self.popover = None;
+                        self.retry();
+                        true
+                    }
+                    _ => false,
+                },
+                Popover::FilePicker | Popover::Context | Popover::Approval => false,
+                Popover::Export => match input {
+                    Input::ScrollUp => {
+                        self.export_options_state.select_previous();
+                        true
+                    }
+                    Input::ScrollDown => {
+                        self.export_options_state.select_next();
+                        true
+                    }
+                    Input::Enter => {
+                        self.export_selected()?;
+                        self.popover = None;
+                        true
+                    }
+                    Input::Export => {
+                        self.popover = None;
+                        true
+                    }
+                    _ => false,
+                },
             };
             return Ok(());
         }
@@ -129,6 +192,18 @@ impl<'a, 't> App<'a, 't> {
                 self.popover = Some(Popover::LlmToolList);
                 true
             }
+            Input::Export => {
+                self.popover = Some(Popover::Export);
+                true
+            }
+            Input::Stop => {
+                self.stop_generation();
+                true
+            }
+            Input::Retry if self.error.is_some() && !self.generating() => {
+                self.retry();
+                true
+            }
             _ => false,
         };
         if handled_global {
@@ -137,20 +212,28 @@ impl<'a, 't> App<'a, 't> {
 
         match self.selected_zone {
             SelectedZone::TextInput => match input {
+                Input::Submit | Input::Enter if self.generating() => {
+                    self.status = Some("Wait for the response or stop it with <C-X>.".to_string());
+                }
                 Input::Submit | Input::Enter => {
                     self.submit_prompt()?;
                 }
+                Input::Back if self.editing.is_some() => self.cancel_edit(),
                 _ => {
-                    self.textarea.input(key_event);
+                    if !self.open_file_picker(key_event) {
+                        self.textarea.input(key_event);
+                    }
                 }
             },
             SelectedZone::ChatHistory => match input {
-                Input::ScrollUp => self.scroll_chat_history(WindowDirection::Up),
-                Input::ScrollDown => self.scroll_chat_history(WindowDirection::Down),
-                Input::Submit => {
-                    if !self.generating {
-                        self.submit_prompt()?;
-                    }
+                Input::ScrollUp => self.select_message(WindowDirection::Up),
+                Input::ScrollDown => self.select_message(WindowDirection::Down),
+                Input::Edit if !self.generating() => self.edit_selected(),
+                Input::Regenerate if !self.generating() => self.regenerate_selected(),
+                Input::BranchPrevious if !self.generating() => self.switch_selected_branch(-1),
+                Input::BranchNext if !self.generating() => self.switch_selected_branch(1),
+                Input::Submit if !self.generating() => {
+                    self.submit_prompt()?;
                 }
                 _ => {}
             },
@@ -161,31 +244,73 @@ impl<'a, 't> App<'a, 't> {
 
     fn submit_prompt(&mut self) -> anyhow::Result<()> {
         let prompt = self.textarea.lines().join("\n");
+        if let Some(command) = ContextCommand::parse(&prompt) {
+            self.textarea = TextArea::default();
+            self.run_context_command(command);
+            return Ok(());
+        }
+        let attachments = match file_picker::mentioned_files(&prompt) {
+            Ok(attachments) => attachments,
+            Err(err) => {
+                self.show_error(err.context("Failed to attach a file mentioned in the prompt."));
+                return Ok(());
+            }
+        };
+        if let Some(index) = self.editing.take() {
+            self.provider.history_mut().truncate(index);
+        }
+        let summary = attachments
+            .iter()
+            .map(|attachment| attachment.summary())
+            .collect::<Vec<_>>()
+            .join(" ");
+        for file in attachments
+            .into_iter()
+            .flat_map(|attachment| attachment.files)
+        {
+            self.provider.add_chat_to_context(file)?;
+        }
+        // The request is built from the history before the prompt, which is sent separately.
+        let request_provider = self.provider.clone();
         self.provider
             .add_chat_to_context(ChatHistoryItem::Chat(ChatData::user(prompt.clone())))?;
         self.textarea = TextArea::default();
-        self.generating = true;
+        self.selected_message = None;
+        self.start_generation(request_provider, prompt);
+        if !summary.is_empty() {
+            self.status = Some(format!(" {summary} "));
+        }
+        Ok(())
+    }
+
+    /// Stream a response to `prompt` from `request_provider` into the conversation.
+    pub(super) fn start_generation(&mut self, request_provider: Provider, prompt: String) {
         self.scrolling_up = false;
-        tokio::spawn(handle_llm_stream(
+        self.last_added_index = None;
+        self.status = None;
+        self.error = None;
+        self.provider.reset_usage();
+        self.generation = Some(tokio::spawn(handle_llm_stream(
             self.event_handler.get_sender(),
-            self.provider.clone(),
+            request_provider,
             prompt,
-        ));
-        Ok(())
+        )));
     }
 
-    fn scroll_chat_history(&mut self, directon: WindowDirection) {
-        match directon {
-            WindowDirection::Up => {
-                self.scrolling_up = true;
-            }
-            WindowDirection::Down => {
-                self.scrollview_state.scroll_down();
-            }
+    /// Abort the response being streamed, keeping what has arrived so far.
+    fn stop_generation(&mut self) {
+        let Some(generation) = self.generation.take() else {
+            return;
         };
+        generation.abort();
+        if let Some(index) = self.last_added_index.take() {
+            self.provider.history_mut().mark_truncated(index);
+        }
+        self.status = Some("Response stopped.".to_string());
     }
 
-    fn exit(&mut self) {
+    pub(super) fn exit(&mut self) {
+        self.stop_generation();
         self.exit = true;
     }
 
@@ -216,6 +341,17 @@ async fn handle_llm_stream(
     mut provider: Provider,
     prompt: String,
 ) {
+    // Compact first so the history view can show it, the request then finds nothing to do.
+    match provider.compact(&prompt).await {
+        Ok(Some(compaction)) => {
+            let _ = tx.send(Event::LlmResponse(LlmResponse::Compacted(compaction)));
+        }
+        Ok(None) => {}
+        Err(err) => {
+            let _ = tx.send(Event::Error(err));
+            return;
+        }
+    }
     let stream = provider.complete_chat_stream(prompt).await;
     let mut stream = match stream {
         Ok(stream) => stream,
@@ -228,20 +364,18 @@ async fn handle_llm_stream(
     while let Some(response) = stream.next().await {
         match response {
             Ok(chunk) => {
-                if tx
-                    .send(Event::LlmResponse(LlmResponse::Chunk(chunk)))
-                    .is_err()
-                {
+                let response = match chunk {
+                    StreamChunk::Text(text) => LlmResponse::Chunk(text),
+                    StreamChunk::Usage(usage) => LlmResponse::Usage(usage),
+                    StreamChunk::ToolCall(call) => LlmResponse::ToolCall(call),
+                };
+                if tx.send(Event::LlmResponse(response)).is_err() {
                     break; // Exit if the receiver is closed
                 }
             }
             Err(e) => {
-                if tx
-                    .send(Event::Error(anyhow::anyhow!("LLM Stream error. {}", e)))
-                    .is_err()
-                {
-                    break; // Exit if the receiver is closed
-                }
+                let _ = tx.send(Event::Error(anyhow::anyhow!("LLM Stream error. {}", e)));
+                return;
             }
         }
     }
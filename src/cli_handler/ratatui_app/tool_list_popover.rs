@@ -1,16 +1,9 @@
 use ratatui::{
     Frame,
-    buffer::Buffer,
     layout::{Margin, Rect},
-    style::{Color, Modifier, Style, Stylize, palette::tailwind::SLATE},
-    symbols,
-    text::Line,
-    widgets::{
-        Block, Borders, Clear, HighlightSpacing, List, ListItem, Paragraph, StatefulWidget, Widget,
-    },
+    style::{Color, Modifier, Style, Stylize},
+    widgets::{Block, Clear, HighlightSpacing, List, ListItem},
 };
-use strum::IntoEnumIterator;
-use tui_popup::{Popup, SizedWrapper};
 
 use crate::provider::LLMTools;
 
@@ -18,9 +11,11 @@ use super::App;
 
 const SELECTED_STYLE: Style = Style::new().bg(Color::Blue).add_modifier(Modifier::BOLD);
 
-#[derive(Debug, Clone, Copy, strum::FromRepr, strum::EnumIter)]
+#[derive(Debug, Clone)]
 pub(super) enum LlmToolEnum {
     Search,
+    /// A local tool, by name.
+    Function(String),
 }
 
 impl LLMTools {
@@ -29,11 +24,16 @@ impl LLMTools {
             LlmToolEnum::Search => {
                 self.search = !self.search;
             }
+            LlmToolEnum::Function(name) => {
+                if !self.functions.remove(&name) {
+                    self.functions.insert(name);
+                }
+            }
         }
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub(super) struct LlmToolItem<'a> {
     llm_item: LlmToolEnum,
     llm_tools: &'a LLMTools,
@@ -51,7 +51,11 @@ impl LlmToolEnum {
 impl<'a> From<LlmToolItem<'a>> for ListItem<'a> {
     fn from(value: LlmToolItem<'a>) -> Self {
         let (display_name, activated) = match value.llm_item {
-            LlmToolEnum::Search => ("Web Search", value.llm_tools.search),
+            LlmToolEnum::Search => ("Web Search".to_string(), value.llm_tools.search),
+            LlmToolEnum::Function(name) => {
+                let activated = value.llm_tools.functions.contains(&name);
+                (name, activated)
+            }
         };
         match activated {
             true => ListItem::new(format!(" ✓ {}", display_name)).fg(Color::Green),
@@ -61,17 +65,29 @@ impl<'a> From<LlmToolItem<'a>> for ListItem<'a> {
 }
 
 impl<'a, 't> App<'a, 't> {
+    /// Options listed in the popover: web search, then every local tool.
+    pub(super) fn llm_tool_options(&self) -> Vec<LlmToolEnum> {
+        std::iter::once(LlmToolEnum::Search)
+            .chain(
+                self.provider
+                    .memory()
+                    .toolbox
+                    .iter()
+                    .map(|tool| LlmToolEnum::Function(tool.name().to_string())),
+            )
+            .collect()
+    }
+
     pub(super) fn llm_options_popup(&mut self, area: Rect, frame: &mut Frame) {
         let popover_rect = area.inner(Margin::new(5, 5));
         frame.render_widget(Clear, popover_rect);
         let block = Block::bordered().title("LLM Options");
 
-        let item_enum = LlmToolEnum::iter().collect::<Vec<_>>();
         // Iterate through all elements in the `items` and stylize them.
-        let items: Vec<ListItem> = item_enum
-            .iter()
-            .enumerate()
-            .map(|(i, item)| ListItem::from(item.with_context(self.provider.flags())))
+        let items: Vec<ListItem> = self
+            .llm_tool_options()
+            .into_iter()
+            .map(|item| ListItem::from(item.with_context(self.provider.flags())))
             .collect();
 
         // Create a List from all list items and highlight the currently selected one
@@ -0,0 +1,148 @@
+use std::time::{Duration, SystemTime};
+
+use anyhow::Context;
+use termimad::MadSkin;
+
+use crate::{
+    export,
+    provider::{ChatHistoryItem, ChatMemory, ChatRole, Provider},
+    session::{self, Session, SessionStore},
+};
+
+use super::{ChatCommand, Cli, CommandState, SessionsCommand};
+
+/// The session a chat command saves its conversation to.
+pub(super) struct ActiveSession {
+    store: SessionStore,
+    name: String,
+}
+
+impl ActiveSession {
+    /// Resolve `--session` and `--continue`, restoring a saved conversation into the provider.
+    /// Interactive chats without either are saved under a generated name so they can be
+    /// continued later; one shot messages are only saved when a session is requested.
+    pub(super) fn open(
+        command: &ChatCommand,
+        interactive: bool,
+        provider: &mut Provider,
+    ) -> anyhow::Result<Option<Self>> {
+        // One shot messages without a session work without a user data dir.
+        if command.session.is_none() && !command.resume && !interactive {
+            return Ok(None);
+        }
+        let store = SessionStore::new()?;
+        let name = match (&command.session, command.resume) {
+            (Some(name), _) => name.clone(),
+            (None, true) => store.latest()?,
+            (None, false) => session::generate_name(),
+        };
+        if store.exists(&name)? {
+            let session = store.load(&name)?;
+            provider.restore_memory(session.memory);
+        }
+        Ok(Some(Self { store, name }))
+    }
+
+    pub(super) fn save(&self, provider: &Provider, state: &CommandState) -> anyhow::Result<()> {
+        if provider.get_history().is_empty() {
+            return Ok(());
+        }
+        let session = Session {
+            provider: state.config.provider.clone(),
+            memory: provider.memory().clone(),
+        };
+        self.store
+            .save(&self.name, &session)
+            .with_context(|| format!("Failed to save session {}.", self.name))
+    }
+}
+
+impl Cli {
+    pub(super) fn handle_sessions_command(
+        command: SessionsCommand,
+        state: &CommandState,
+    ) -> anyhow::Result<()> {
+        let store = SessionStore::new()?;
+        match command {
+            SessionsCommand::List => {
+                let now = SystemTime::now();
+                for summary in store.list()? {
+                    let messages = store
+                        .load(&summary.name)
+                        .map(|session| format!("{} messages", session.memory.history.len()))
+                        .unwrap_or_else(|_| "unreadable".to_string());
+                    let age = now.duration_since(summary.modified).unwrap_or_default();
+                    println!("{:<24} {:<14} {}", summary.name, messages, format_age(age));
+                }
+            }
+            SessionsCommand::Show { name } => {
+                let session = store.load(&name)?;
+                let transcript = format_transcript(&session.memory);
+                if state.quiet {
+                    print!("{}", transcript);
+                } else {
+                    MadSkin::default().print_text(&transcript);
+                }
+            }
+            SessionsCommand::Delete { name } => {
+                store.delete(&name)?;
+                println!("Deleted session {name}.");
+            }
+            SessionsCommand::Export {
+                name,
+                format,
+                output,
+            } => {
+                let session = store.load(&name)?;
+                let exported = export::export(&session.memory, &name, format)?;
+                match output {
+                    Some(path) => {
+                        std::fs::write(&path, exported)
+                            .with_context(|| format!("Failed to write export to {:?}.", path))?;
+                        if !state.quiet {
+                            println!("Exported session {name} to {}.", path.display());
+                        }
+                    }
+                    None => print!("{}", exported),
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn format_transcript(memory: &ChatMemory) -> String {
+    let mut transcript = String::new();
+    if let Some(system_prompt) = &memory.system_prompt {
+        transcript.push_str(&format!(
+            "**{}**\n\n{}\n\n",
+            ChatRole::System.display(),
+            system_prompt
+        ));
+    }
+    for item in &memory.history {
+        match item {
+            ChatHistoryItem::Chat(chat) => {
+                transcript.push_str(&format!("**{}**\n\n{}\n\n", chat.role.display(), chat.text))
+            }
+            ChatHistoryItem::FileUpload(file) => {
+                transcript.push_str(&format!("*File upload: {}*\n\n", file.relative_filepath))
+            }
+            ChatHistoryItem::ToolCall(call) => transcript.push_str(&format!(
+                "*Tool call: {}({})*\n\n",
+                call.name, call.arguments
+            )),
+        }
+    }
+    transcript
+}
+
+fn format_age(age: Duration) -> String {
+    let seconds = age.as_secs();
+    match seconds {
+        0..60 => "just now".to_string(),
+        60..3600 => format!("{}m ago", seconds / 60),
+        3600..86400 => format!("{}h ago", seconds / 3600),
+        _ => format!("{}d ago", seconds / 86400),
+    }
+}
@@ -1,4 +1,5 @@
-use config;
+use std::{collections::HashMap, path::PathBuf};
+
 use serde::{Deserialize, Serialize};
 use tracing::{instrument, trace};
 
@@ -9,33 +10,190 @@ pub struct Configuration {
     pub provider: String,
     pub provider_opts: ProviderOpts,
     pub tools: Option<ToolOptions>,
+    pub api_keys: ApiKeyOptions,
+    pub compaction: CompactionOptions,
+    /// MCP servers whose tools are offered to the model, by name.
+    #[serde(default)]
+    pub mcp_servers: HashMap<String, McpServerOptions>,
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct ProviderOpts {
     pub gemini: GeminiProviderOpts,
+    pub openai: OpenAIProviderOpts,
+    pub claude: ClaudeProviderOpts,
+    pub ollama: OllamaProviderOpts,
+    /// Named OpenAI compatible endpoints, selected by using their name as the `provider`.
+    #[serde(default)]
+    pub openai_compatible: HashMap<String, OpenAICompatibleProviderOpts>,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct OnlineProviderOpts {
     pub url: String,
     pub model: String,
+    /// Tokens the model accepts, for models the built in table does not know.
+    #[serde(default)]
+    pub context_window: Option<u64>,
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct ToolOptions {
     pub search_default: Option<bool>,
+    /// Local tools enabled for new conversations, by name.
+    pub functions_default: Option<Vec<String>>,
+    /// Shell commands the `run_shell` tool may run without asking, e.g. `git status`.
+    pub allowed_commands: Option<Vec<String>>,
+    /// Tools declared by the user, each running a shell command.
+    #[serde(default)]
+    pub custom: Vec<CustomToolOptions>,
+}
+
+/// A tool that runs `command` with `sh -c`, handing it the model's arguments.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CustomToolOptions {
+    /// What the model calls the tool by: a letter or underscore followed by letters, digits,
+    /// `_`, `.` or `-`, at most 64 in all.
+    #[serde(deserialize_with = "tool_name")]
+    pub name: String,
+    pub description: String,
+    /// JSON schema of the arguments object. The tool takes no arguments when left out.
+    pub parameters: Option<serde_json::Value>,
+    pub command: String,
+    #[serde(default)]
+    pub input: ArgumentInput,
+    /// Ask before each run, as for the built in shell tool. Read only tools can turn it off.
+    #[serde(default = "ask_before_running")]
+    pub confirm: bool,
+}
+
+fn ask_before_running() -> bool {
+    true
+}
+
+/// Whether every provider accepts `name` for a function: a letter or underscore followed by
+/// letters, digits, '_', '.' or '-', at most 64 in all.
+pub fn is_valid_tool_name(name: &str) -> bool {
+    name.len() <= 64
+        && name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'))
+}
+
+fn tool_name<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    let name = String::deserialize(deserializer)?;
+    if !is_valid_tool_name(&name) {
+        return Err(serde::de::Error::custom(format!(
+            "invalid tool name {name:?}, use a letter or underscore followed by letters, digits, \
+            '_', '.' or '-', at most 64 in all"
+        )));
+    }
+    Ok(name)
+}
+
+/// How a custom tool's command receives the arguments the model chose.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ArgumentInput {
+    /// The arguments object as JSON on stdin.
+    #[default]
+    Stdin,
+    /// Each argument in an `LLCLI_ARG_<NAME>` environment variable.
+    Env,
+}
+
+/// An MCP server, started with `command` and spoken to over stdio, or reached at `url` over
+/// streamable HTTP.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct McpServerOptions {
+    pub command: Option<String>,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Extra environment variables for `command`.
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    pub url: Option<String>,
+    /// Extra headers sent with every request to `url`, e.g. for authentication.
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    /// Believe the server when it declares a tool read only, running those tools without asking.
+    #[serde(default)]
+    pub trusted: bool,
+    /// Tools, by their name on the server, that run without asking whether or not it is trusted.
+    #[serde(default)]
+    pub read_only_tools: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ApiKeyOptions {
+    /// Sources consulted in order when looking up a provider's api key.
+    pub sources: Vec<KeySource>,
+    /// Overrides the default credentials file location in the config dir.
+    pub credentials_file: Option<PathBuf>,
+    /// Command per provider whose stdout is the api key, e.g. `pass show gemini`.
+    #[serde(default)]
+    pub key_command: HashMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum KeySource {
+    /// `LLCLI_<PROVIDER>_API_KEY` environment variables.
+    Env,
+    /// A `0600` TOML credentials file mapping provider names to keys.
+    File,
+    /// The provider's `key_command`.
+    Command,
+    /// The system keychain.
+    Keyring,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CompactionOptions {
+    pub strategy: CompactionStrategy,
+    /// Share of the context window a request may fill before older messages are compacted,
+    /// leaving the rest for the response.
+    pub threshold: f64,
+}
+
+/// How a conversation that no longer fits the model's context window is shortened.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CompactionStrategy {
+    /// Send the whole conversation, leaving the provider to reject requests that are too long.
+    Off,
+    /// Leave out the oldest messages and attached files.
+    DropOldest,
+    /// Leave out the oldest messages but keep every attached file.
+    KeepFiles,
+    /// Replace the oldest messages with a summary written by the model, keeping attached files.
+    Summarise,
 }
 
 pub struct ConfigManager {
     pub config: Configuration,
 }
 
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Could not determine the user config directory.")]
+    NoConfigDir,
+    #[error("Config path {0:?} is not valid unicode.")]
+    InvalidPath(std::path::PathBuf),
+    #[error("Invalid configuration in {path:?}.")]
+    Invalid {
+        path: std::path::PathBuf,
+        #[source]
+        source: config::ConfigError,
+    },
+}
+
 impl ConfigManager {
     #[instrument]
-    pub fn new() -> ConfigManager {
+    pub fn new() -> Result<ConfigManager, Error> {
         trace!("Initialising Config.");
-        let config_dir = dirs::config_dir().expect("Failed to retrieve config dir path.");
+        let config_dir = dirs::config_dir().ok_or(Error::NoConfigDir)?;
         let settings_path = config_dir.join("llcli.toml");
         let settings = config::Config::builder()
             .add_source(config::File::from_str(
@@ -46,17 +204,19 @@ impl ConfigManager {
                 config::File::with_name(
                     settings_path
                         .to_str()
-                        .expect("Failed to unwrap settings file path."),
+                        .ok_or_else(|| Error::InvalidPath(settings_path.clone()))?,
                 )
                 .required(false),
             )
             .build()
-            .expect("Failed to build config")
-            .try_deserialize::<Configuration>()
-            .expect("Failed to deserialise config file");
+            .and_then(|settings| settings.try_deserialize::<Configuration>())
+            .map_err(|source| Error::Invalid {
+                path: settings_path,
+                source,
+            })?;
 
         trace!("Config Initialisation successful");
-        Self { config: settings }
+        Ok(Self { config: settings })
     }
 }
 
@@ -67,3 +227,50 @@ pub struct GeminiProviderOpts {
     #[serde(flatten)]
     pub online_opts: OnlineProviderOpts,
 }
+
+#[derive(Serialize, Deserialize)]
+pub struct OpenAIProviderOpts {
+    #[serde(flatten)]
+    pub online_opts: OnlineProviderOpts,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ClaudeProviderOpts {
+    #[serde(flatten)]
+    pub online_opts: OnlineProviderOpts,
+    pub max_tokens: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct OllamaProviderOpts {
+    #[serde(flatten)]
+    pub online_opts: OnlineProviderOpts,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct OpenAICompatibleProviderOpts {
+    #[serde(flatten)]
+    pub online_opts: OnlineProviderOpts,
+    #[serde(default)]
+    pub auth: AuthScheme,
+    /// Extra headers sent with every request.
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    /// Ask for token counts at the end of a streamed response with `stream_options`, which
+    /// not every server accepts.
+    #[serde(default)]
+    pub stream_usage: bool,
+}
+
+/// How the api key is attached to requests for an OpenAI compatible endpoint.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthScheme {
+    /// `Authorization: Bearer <key>`.
+    #[default]
+    Bearer,
+    /// The raw key in the named header, e.g. `{ header = "api-key" }`.
+    Header(String),
+    /// No authentication, and no api key lookup.
+    None,
+}
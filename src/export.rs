@@ -0,0 +1,325 @@
+use anyhow::Context;
+use pulldown_cmark::{CowStr, Event, Options, Parser, Tag};
+use serde_json::json;
+
+use crate::{
+    provider::{ChatHistoryItem, ChatMemory, ChatRole},
+    session,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum, strum::EnumIter, strum::FromRepr)]
+pub enum ExportFormat {
+    #[value(alias = "md")]
+    Markdown,
+    Json,
+    Html,
+}
+
+impl ExportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Markdown => "md",
+            Self::Json => "json",
+            Self::Html => "html",
+        }
+    }
+
+    pub fn display(&self) -> &'static str {
+        match self {
+            Self::Markdown => "Markdown",
+            Self::Json => "JSON",
+            Self::Html => "HTML",
+        }
+    }
+}
+
+/// File name in the current directory for an export that was not given a path.
+pub fn default_file_name(format: ExportFormat) -> String {
+    format!("llcli_{}.{}", session::generate_name(), format.extension())
+}
+
+/// Render the active branch of a conversation for pasting into documents or processing with
+/// other tools.
+pub fn export(memory: &ChatMemory, title: &str, format: ExportFormat) -> anyhow::Result<String> {
+    match format {
+        ExportFormat::Markdown => Ok(to_markdown(memory, title)),
+        ExportFormat::Json => serde_json::to_string_pretty(&json!({
+            "title": title,
+            "system_prompt": memory.system_prompt,
+            "messages": &*memory.history,
+        }))
+        .context("Failed to serialise conversation."),
+        ExportFormat::Html => Ok(to_html(memory, title)),
+    }
+}
+
+fn to_markdown(memory: &ChatMemory, title: &str) -> String {
+    let mut markdown = format!("# {title}\n\n");
+    if let Some(system_prompt) = &memory.system_prompt {
+        markdown.push_str(&format!(
+            "## {}\n\n{}\n\n",
+            ChatRole::System.display(),
+            system_prompt.trim_end()
+        ));
+    }
+    for item in &memory.history {
+        match item {
+            ChatHistoryItem::Chat(chat) => markdown.push_str(&format!(
+                "## {}\n\n{}\n\n",
+                chat.role.display(),
+                chat.text.trim_end()
+            )),
+            ChatHistoryItem::FileUpload(file) => {
+                let fence = code_fence(&file.text);
+                markdown.push_str(&format!(
+                    "<details>\n<summary>File: {}</summary>\n\n{fence}\n{}\n{fence}\n\n</details>\n\n",
+                    escape_html(&file.relative_filepath),
+                    file.text.trim_end()
+                ));
+            }
+            ChatHistoryItem::ToolCall(call) => {
+                let fence = code_fence(&call.output);
+                markdown.push_str(&format!(
+                    "<details>\n<summary>Tool call: {}({})</summary>\n\n{fence}\n{}\n{fence}\n\n</details>\n\n",
+                    escape_html(&call.name),
+                    escape_html(&call.arguments.to_string()),
+                    call.output.trim_end()
+                ));
+            }
+        }
+    }
+    markdown
+}
+
+fn to_html(memory: &ChatMemory, title: &str) -> String {
+    let mut body = String::new();
+    if let Some(system_prompt) = &memory.system_prompt {
+        body.push_str(&html_message("system", ChatRole::System, system_prompt));
+    }
+    for item in &memory.history {
+        match item {
+            ChatHistoryItem::Chat(chat) => {
+                let class = match chat.role {
+                    ChatRole::User => "user",
+                    ChatRole::Model => "model",
+                    ChatRole::System => "system",
+                    ChatRole::Context => "context",
+                };
+                body.push_str(&html_message(class, chat.role, &chat.text));
+            }
+            ChatHistoryItem::FileUpload(file) => body.push_str(&format!(
+                "<details class=\"file\"><summary>File: {}</summary><pre><code>{}</code></pre></details>\n",
+                escape_html(&file.relative_filepath),
+                escape_html(&file.text)
+            )),
+            ChatHistoryItem::ToolCall(call) => body.push_str(&format!(
+                "<details class=\"tool\"><summary>Tool call: {}({})</summary><pre><code>{}</code></pre></details>\n",
+                escape_html(&call.name),
+                escape_html(&call.arguments.to_string()),
+                escape_html(&call.output)
+            )),
+        }
+    }
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<title>{title}</title>
+<style>
+body {{ font-family: system-ui, sans-serif; max-width: 50rem; margin: 2rem auto; padding: 0 1rem; line-height: 1.5; }}
+section, details {{ border: 1px solid #ddd; border-radius: 6px; padding: 0 1rem; margin: 1rem 0; }}
+section h2 {{ font-size: 1rem; margin: 0.75rem 0 0; }}
+.user h2 {{ color: #555; }}
+.model h2 {{ color: #1f6feb; }}
+.system h2 {{ color: #8250df; }}
+.context h2 {{ color: #9a6700; }}
+details {{ padding: 0.5rem 1rem; }}
+pre {{ background: #f6f8fa; padding: 0.75rem; overflow-x: auto; }}
+code {{ font-family: ui-monospace, monospace; }}
+</style>
+</head>
+<body>
+<h1>{title}</h1>
+{body}</body>
+</html>
+"#,
+        title = escape_html(title),
+    )
+}
+
+fn html_message(class: &str, role: ChatRole, text: &str) -> String {
+    format!(
+        "<section class=\"{class}\"><h2>{}</h2>\n{}</section>\n",
+        role.display(),
+        markdown_to_html(text)
+    )
+}
+
+/// Render markdown, escaping any raw HTML in it and pointing links with other schemes than
+/// http, https and mailto at `#`, so the page stays inert.
+fn markdown_to_html(markdown: &str) -> String {
+    let parser = Parser::new_ext(
+        markdown,
+        Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH,
+    )
+    .map(|event| match event {
+        Event::Html(html) | Event::InlineHtml(html) => Event::Text(html),
+        Event::Start(Tag::Link {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) => Event::Start(Tag::Link {
+            link_type,
+            dest_url: safe_url(dest_url),
+            title,
+            id,
+        }),
+        Event::Start(Tag::Image {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) => Event::Start(Tag::Image {
+            link_type,
+            dest_url: safe_url(dest_url),
+            title,
+            id,
+        }),
+        event => event,
+    });
+    let mut html = String::new();
+    pulldown_cmark::html::push_html(&mut html, parser);
+    html
+}
+
+/// `url` when it is relative or uses a scheme that cannot run script, otherwise `#`.
+fn safe_url(url: CowStr<'_>) -> CowStr<'_> {
+    let scheme = url
+        .split_once(':')
+        .map(|(scheme, _)| scheme)
+        .filter(|scheme| !scheme.contains(['/', '?', '#']));
+    match scheme {
+        None => url,
+        Some(scheme)
+            if ["http", "https", "mailto"]
+                .iter()
+                .any(|safe| scheme.eq_ignore_ascii_case(safe)) =>
+        {
+            url
+        }
+        Some(_) => CowStr::Borrowed("#"),
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// A backtick fence longer than any run of backticks in `text`.
+fn code_fence(text: &str) -> String {
+    let longest_run = text
+        .split(|c| c != '`')
+        .map(str::len)
+        .max()
+        .unwrap_or_default();
+    "`".repeat(longest_run.max(2) + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn safe(url: &str) -> String {
+        safe_url(CowStr::Borrowed(url)).to_string()
+    }
+
+    #[test]
+    fn keeps_relative_urls() {
+        for url in [
+            "notes.md",
+            "../docs/a:b.md",
+            "/path?q=a:b",
+            "#section:2",
+            "",
+        ] {
+            assert_eq!(safe(url), url);
+        }
+    }
+
+    #[test]
+    fn keeps_http_https_and_mailto() {
+        for url in [
+            "http://example.com",
+            "https://example.com/a?b=c:d",
+            "HTTPS://EXAMPLE.COM",
+            "mailto:someone@example.com",
+        ] {
+            assert_eq!(safe(url), url);
+        }
+    }
+
+    #[test]
+    fn neutralises_other_schemes() {
+        for url in [
+            "javascript:alert(1)",
+            "JavaScript:alert(1)",
+            "data:text/html,<script>alert(1)</script>",
+            "vbscript:msgbox(1)",
+            "file:///etc/passwd",
+            "java\tscript:alert(1)",
+        ] {
+            assert_eq!(safe(url), "#", "{url:?} was kept");
+        }
+    }
+
+    #[test]
+    fn neutralises_links_and_images_in_markdown() {
+        let html = markdown_to_html(
+            "[click](javascript:alert(1)) ![img](data:image/svg+xml,x) [ok](https://example.com) \
+             [entity](javascript&#58;alert(1))",
+        );
+
+        assert!(!html.contains("javascript"), "{html}");
+        assert!(!html.contains("data:"), "{html}");
+        assert!(html.contains(r#"href="https://example.com""#), "{html}");
+    }
+
+    #[test]
+    fn escapes_raw_html_in_markdown() {
+        let html = markdown_to_html("<script>alert(1)</script>\n\ntext <b onclick=\"x\">bold</b>");
+
+        assert!(!html.contains("<script>"), "{html}");
+        assert!(!html.contains("<b "), "{html}");
+        assert!(html.contains("&lt;script&gt;"), "{html}");
+    }
+
+    #[test]
+    fn escapes_html_special_characters() {
+        assert_eq!(
+            escape_html(r#"<a href="x" title='y'>Tom & Jerry</a>"#),
+            "&lt;a href=&quot;x&quot; title=&#39;y&#39;&gt;Tom &amp; Jerry&lt;/a&gt;"
+        );
+    }
+
+    #[test]
+    fn leaves_plain_text_unescaped() {
+        assert_eq!(
+            escape_html("plain text, ünïcode ✓"),
+            "plain text, ünïcode ✓"
+        );
+        assert_eq!(escape_html(""), "");
+    }
+}
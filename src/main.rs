@@ -1,3 +1,5 @@
+use std::process::ExitCode;
+
 use clap::Parser;
 use cli_handler::Cli;
 use configuration::ConfigManager;
@@ -5,7 +7,10 @@ use tracing_appender::non_blocking::WorkerGuard;
 
 mod cli_handler;
 mod configuration;
+mod export;
 mod provider;
+mod session;
+mod tools;
 
 use provider::APIKeyManager;
 
@@ -24,14 +29,23 @@ fn init_tracing() -> WorkerGuard {
 }
 
 #[tokio::main]
-async fn main() -> anyhow::Result<()> {
+async fn main() -> ExitCode {
     let _guard = init_tracing();
 
-    let config = ConfigManager::new();
-    let api_key_manager = APIKeyManager::new();
-
     let cli = Cli::parse();
-    let result = cli.handle_command(&config.config, &api_key_manager).await;
+    match run(cli).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            tracing::error!("Exiting with error: {:?}", err);
+            eprintln!("Error: {:?}", err);
+            ExitCode::from(cli_handler::exit_code(&err))
+        }
+    }
+}
+
+async fn run(cli: Cli) -> anyhow::Result<()> {
+    let config = ConfigManager::new()?;
+    let api_key_manager = APIKeyManager::new(&config.config.api_keys);
 
-    result
+    cli.handle_command(&config.config, &api_key_manager).await
 }
@@ -0,0 +1,105 @@
+//! Api key sources that work without a desktop keychain, for CI runners, containers and
+//! SSH sessions.
+
+use std::{collections::HashMap, path::Path, process::Command};
+
+use crate::provider::{Error, Result};
+
+/// Environment variable holding the key for `provider`, e.g. `LLCLI_GEMINI_API_KEY`.
+pub fn env_var_name(provider: &str) -> String {
+    let provider = provider
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>();
+    format!("LLCLI_{provider}_API_KEY")
+}
+
+pub(super) fn from_env(provider: &str) -> Option<String> {
+    std::env::var(env_var_name(provider))
+        .ok()
+        .filter(|key| !key.is_empty())
+}
+
+pub(super) fn from_file(provider: &str, path: Option<&Path>) -> Result<Option<String>> {
+    let Some(path) = path.filter(|path| path.exists()) else {
+        return Ok(None);
+    };
+    ensure_private(path)?;
+
+    let keys = config::Config::builder()
+        .add_source(config::File::from(path).format(config::FileFormat::Toml))
+        .build()
+        .and_then(|keys| keys.try_deserialize::<HashMap<String, String>>())
+        .map_err(|source| Error::CredentialsFile {
+            path: path.to_path_buf(),
+            source,
+        })?;
+    Ok(keys.get(provider).cloned())
+}
+
+#[cfg(unix)]
+fn ensure_private(path: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mode = std::fs::metadata(path)
+        .map_err(|err| Error::CredentialsFile {
+            path: path.to_path_buf(),
+            source: config::ConfigError::Foreign(Box::new(err)),
+        })?
+        .permissions()
+        .mode();
+    if mode & 0o077 != 0 {
+        return Err(Error::InsecureCredentialsFile {
+            path: path.to_path_buf(),
+        });
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn ensure_private(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+pub(super) fn from_command(provider: &str, command: &str) -> Result<String> {
+    let key_command_error = |reason: String| Error::KeyCommand {
+        provider: provider.to_string(),
+        reason,
+    };
+
+    let output = if cfg!(windows) {
+        Command::new("cmd").args(["/C", command]).output()
+    } else {
+        Command::new("sh").args(["-c", command]).output()
+    }
+    .map_err(|err| key_command_error(format!("Could not run `{command}`. {err}")))?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(key_command_error(
+            format!(
+                "`{command}` exited with {}. {}",
+                output.status,
+                stderr.trim()
+            )
+            .trim_end()
+            .to_string(),
+        ));
+    }
+    let key = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_string();
+    if key.is_empty() {
+        return Err(key_command_error(format!("`{command}` printed no key.")));
+    }
+    Ok(key)
+}
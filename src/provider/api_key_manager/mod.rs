@@ -1,42 +1,114 @@
-use tracing::instrument;
+mod key_source;
+
+use std::{collections::HashMap, path::PathBuf};
+
+use tracing::{instrument, trace};
+
+use crate::configuration::{ApiKeyOptions, KeySource};
 
 use super::{Error, Result};
+pub use key_source::env_var_name;
 
 #[derive(Debug)]
 pub struct APIKeyManager {
     user_name: String,
+    sources: Vec<KeySource>,
+    credentials_file: Option<PathBuf>,
+    key_commands: HashMap<String, String>,
 }
 
-const SERVICE_NAME: &'static str = "llmcli_rs";
+const SERVICE_NAME: &str = "llmcli_rs";
+const CREDENTIALS_FILE: &str = "llcli_credentials.toml";
 
 impl APIKeyManager {
     #[instrument(ret)]
-    pub fn new() -> Self {
+    pub fn new(options: &ApiKeyOptions) -> Self {
         Self {
             user_name: whoami::username(),
+            sources: options.sources.clone(),
+            credentials_file: options
+                .credentials_file
+                .clone()
+                .or_else(|| dirs::config_dir().map(|dir| dir.join(CREDENTIALS_FILE))),
+            key_commands: options.key_command.clone(),
         }
     }
+
+    /// Look the key up in each configured source in order, returning the first one found.
     pub fn fetch_api_key(&self, provider: &str) -> Result<String> {
-        let entry = keyring::Entry::new_with_target(provider, SERVICE_NAME, &self.user_name)
-            .expect("Failed to create keyring entry with target.");
-        match entry.get_password() {
-            Ok(password) => {
-                entry.set_password(password.as_str())?;
-                Ok(password)
+        self.find_api_key(provider)?
+            .map(|(_source, api_key)| api_key)
+            .ok_or_else(|| Error::NoApiKey {
+                provider: provider.to_string(),
+                env_var: env_var_name(provider),
+            })
+    }
+
+    /// Like [`APIKeyManager::fetch_api_key`], but also reports which source supplied the key.
+    pub fn find_api_key(&self, provider: &str) -> Result<Option<(KeySource, String)>> {
+        for source in &self.sources {
+            let api_key = match source {
+                KeySource::Env => key_source::from_env(provider),
+                KeySource::File => {
+                    key_source::from_file(provider, self.credentials_file.as_deref())?
+                }
+                KeySource::Command => self
+                    .key_commands
+                    .get(provider)
+                    .map(|command| key_source::from_command(provider, command))
+                    .transpose()?,
+                KeySource::Keyring => self.read_keyring(provider)?,
+            };
+            if let Some(api_key) = api_key {
+                trace!("Using {:?} api key for provider {}", source, provider);
+                return Ok(Some((*source, api_key)));
             }
-            Err(err) => match err {
-                keyring::Error::NoEntry => Err(Error::NoApiKey),
-                _ => Err(err.into()),
-            },
         }
+        Ok(None)
     }
 
     pub fn set_api_key(&self, provider: &str, key: &str) -> Result<String> {
-        let entry = keyring::Entry::new_with_target(provider, SERVICE_NAME, &self.user_name)
-            .expect("Failed to create keyring entry with target.");
-        match entry.set_password(key) {
-            Ok(_) => Ok(key.to_string()),
-            Err(err) => Err(err.into()),
+        self.entry(provider)?
+            .set_password(key)
+            .map_err(|source| keyring_error(provider, source))?;
+        Ok(key.to_string())
+    }
+
+    /// Remove the provider's key from the keyring. Keys from other sources are left alone.
+    pub fn delete_api_key(&self, provider: &str) -> Result<()> {
+        match self.entry(provider)?.delete_credential() {
+            Ok(()) => Ok(()),
+            Err(keyring::Error::NoEntry) => Err(Error::NoApiKey {
+                provider: provider.to_string(),
+                env_var: env_var_name(provider),
+            }),
+            Err(source) => Err(keyring_error(provider, source)),
         }
     }
+
+    fn read_keyring(&self, provider: &str) -> Result<Option<String>> {
+        let entry = self.entry(provider)?;
+        match entry.get_password() {
+            Ok(password) => {
+                entry
+                    .set_password(password.as_str())
+                    .map_err(|source| keyring_error(provider, source))?;
+                Ok(Some(password))
+            }
+            Err(keyring::Error::NoEntry) => Ok(None),
+            Err(source) => Err(keyring_error(provider, source)),
+        }
+    }
+
+    fn entry(&self, provider: &str) -> Result<keyring::Entry> {
+        keyring::Entry::new_with_target(provider, SERVICE_NAME, &self.user_name)
+            .map_err(|source| keyring_error(provider, source))
+    }
+}
+
+fn keyring_error(provider: &str, source: keyring::Error) -> Error {
+    Error::Keychain {
+        provider: provider.to_string(),
+        source,
+    }
 }
@@ -0,0 +1,430 @@
+use std::ops::Deref;
+
+use serde::{Deserialize, Serialize};
+
+use super::{ChatData, ChatHistoryItem};
+
+/// Conversation history kept as a tree, so editing a message or regenerating a reply starts a
+/// new branch instead of discarding the old one.
+///
+/// Every node remembers which of its children is active, and following those from the active
+/// root gives the active branch. That branch is what providers send and what the TUI shows;
+/// it is available as a slice by dereferencing.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(try_from = "ChatHistoryData", into = "ChatHistoryData")]
+pub(crate) struct ChatHistory {
+    nodes: Vec<ChatNode>,
+    roots: Vec<usize>,
+    active_root: Option<usize>,
+    /// Node ids on the active branch, root first.
+    path: Vec<usize>,
+    /// Items on the active branch, kept in step with `path`.
+    items: Vec<ChatHistoryItem>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ChatNode {
+    item: ChatHistoryItem,
+    parent: Option<usize>,
+    children: Vec<usize>,
+    active_child: Option<usize>,
+}
+
+/// A message's position among the alternatives at its point in the conversation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct BranchPosition {
+    pub(crate) index: usize,
+    pub(crate) count: usize,
+}
+
+impl ChatHistory {
+    /// Add an item to the end of the active branch, returning its index in the branch.
+    pub(crate) fn push(&mut self, item: ChatHistoryItem) -> usize {
+        let id = self.nodes.len();
+        let parent = self.path.last().copied();
+        self.nodes.push(ChatNode {
+            item: item.clone(),
+            parent,
+            children: Vec::new(),
+            active_child: None,
+        });
+        match parent {
+            Some(parent) => {
+                self.nodes[parent].children.push(id);
+                self.nodes[parent].active_child = Some(id);
+            }
+            None => {
+                self.roots.push(id);
+                self.active_root = Some(id);
+            }
+        }
+        self.path.push(id);
+        self.items.push(item);
+        self.items.len() - 1
+    }
+
+    /// Append text to a chat on the active branch, e.g. a streamed response chunk.
+    pub(crate) fn append_text(&mut self, index: usize, text: &str) -> Option<()> {
+        self.update_chat(index, |chat| chat.text.push_str(text))
+    }
+
+    /// Mark a chat on the active branch as a response that was stopped part way through.
+    pub(crate) fn mark_truncated(&mut self, index: usize) -> Option<()> {
+        self.update_chat(index, |chat| chat.truncated = true)
+    }
+
+    /// Shorten the active branch to its first `len` items. The dropped items stay in the tree,
+    /// and become an alternative branch once something new is pushed in their place.
+    pub(crate) fn truncate(&mut self, len: usize) {
+        if len >= self.path.len() {
+            return;
+        }
+        match len.checked_sub(1).map(|index| self.path[index]) {
+            Some(parent) => self.nodes[parent].active_child = None,
+            None => self.active_root = None,
+        }
+        self.path.truncate(len);
+        self.items.truncate(len);
+    }
+
+    /// Swap the item at `index` on the active branch for a new one, keeping its place in the tree.
+    pub(crate) fn replace(&mut self, index: usize, item: ChatHistoryItem) -> Option<()> {
+        let id = *self.path.get(index)?;
+        self.nodes[id].item = item.clone();
+        self.items[index] = item;
+        Some(())
+    }
+
+    /// Remove the item at `index` from the conversation. Whatever followed it, on every branch,
+    /// now follows the item before it.
+    pub(crate) fn remove(&mut self, index: usize) -> Option<ChatHistoryItem> {
+        let id = *self.path.get(index)?;
+        let removed = self.nodes[id].clone();
+        for child in &removed.children {
+            self.nodes[*child].parent = removed.parent;
+        }
+        let (siblings, active) = match removed.parent {
+            Some(parent) => {
+                let node = &mut self.nodes[parent];
+                (&mut node.children, &mut node.active_child)
+            }
+            None => (&mut self.roots, &mut self.active_root),
+        };
+        if let Some(position) = siblings.iter().position(|sibling| *sibling == id) {
+            siblings.splice(position..=position, removed.children.iter().copied());
+        }
+        if *active == Some(id) {
+            *active = removed.active_child;
+        }
+        self.delete_node(id);
+        self.rebuild();
+        Some(removed.item)
+    }
+
+    /// Where the item at `index` sits among its alternatives.
+    pub(crate) fn branch_position(&self, index: usize) -> Option<BranchPosition> {
+        let id = *self.path.get(index)?;
+        let siblings = self.siblings(id);
+        Some(BranchPosition {
+            index: siblings.iter().position(|sibling| *sibling == id)?,
+            count: siblings.len(),
+        })
+    }
+
+    /// Make a different alternative active at `index`, moving `offset` places among them.
+    /// Returns whether the active branch changed.
+    pub(crate) fn switch_branch(&mut self, index: usize, offset: isize) -> bool {
+        let Some(id) = self.path.get(index).copied() else {
+            return false;
+        };
+        let siblings = self.siblings(id);
+        let Some(position) = siblings.iter().position(|sibling| *sibling == id) else {
+            return false;
+        };
+        let Some(target) = position
+            .checked_add_signed(offset)
+            .and_then(|target| siblings.get(target).copied())
+        else {
+            return false;
+        };
+        match self.nodes[id].parent {
+            Some(parent) => self.nodes[parent].active_child = Some(target),
+            None => self.active_root = Some(target),
+        }
+        self.rebuild();
+        true
+    }
+
+    pub(crate) fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    fn update_chat(&mut self, index: usize, update: impl Fn(&mut ChatData)) -> Option<()> {
+        let id = *self.path.get(index)?;
+        for item in [&mut self.nodes[id].item, &mut self.items[index]] {
+            match item {
+                ChatHistoryItem::FileUpload(_) | ChatHistoryItem::ToolCall(_) => {}
+                ChatHistoryItem::Chat(chat) => update(chat),
+            }
+        }
+        Some(())
+    }
+
+    /// Drop a node nothing refers to any more, shifting the ids after it down by one.
+    fn delete_node(&mut self, id: usize) {
+        self.nodes.remove(id);
+        let shift = |other: &mut usize| {
+            if *other > id {
+                *other -= 1;
+            }
+        };
+        for node in &mut self.nodes {
+            node.parent.iter_mut().for_each(shift);
+            node.children.iter_mut().for_each(shift);
+            node.active_child.iter_mut().for_each(shift);
+        }
+        self.roots.iter_mut().for_each(shift);
+        self.active_root.iter_mut().for_each(shift);
+    }
+
+    fn siblings(&self, id: usize) -> &[usize] {
+        match self.nodes[id].parent {
+            Some(parent) => &self.nodes[parent].children,
+            None => &self.roots,
+        }
+    }
+
+    fn rebuild(&mut self) {
+        self.path.clear();
+        let mut next = self.active_root;
+        // Bounded by the node count so a corrupt file with a cycle cannot loop forever.
+        while let Some(id) = next.filter(|_| self.path.len() < self.nodes.len()) {
+            self.path.push(id);
+            next = self.nodes[id].active_child;
+        }
+        self.items = self
+            .path
+            .iter()
+            .map(|id| self.nodes[*id].item.clone())
+            .collect();
+    }
+}
+
+impl Deref for ChatHistory {
+    type Target = Vec<ChatHistoryItem>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.items
+    }
+}
+
+impl<'a> IntoIterator for &'a ChatHistory {
+    type Item = &'a ChatHistoryItem;
+    type IntoIter = std::slice::Iter<'a, ChatHistoryItem>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.items.iter()
+    }
+}
+
+/// Serialised form of [`ChatHistory`]: the tree, without the active branch derived from it.
+#[derive(Serialize, Deserialize)]
+struct ChatHistoryData {
+    nodes: Vec<ChatNode>,
+    roots: Vec<usize>,
+    active_root: Option<usize>,
+}
+
+impl TryFrom<ChatHistoryData> for ChatHistory {
+    type Error = String;
+
+    fn try_from(data: ChatHistoryData) -> Result<Self, Self::Error> {
+        let ChatHistoryData {
+            nodes,
+            roots,
+            active_root,
+        } = data;
+        let in_range = |id: &usize| *id < nodes.len();
+        let valid = roots.iter().all(in_range)
+            && active_root.iter().all(in_range)
+            && nodes.iter().all(|node| {
+                node.children.iter().all(in_range)
+                    && node.parent.iter().all(in_range)
+                    && node.active_child.iter().all(in_range)
+            });
+        if !valid {
+            return Err("chat history refers to a message that does not exist".to_string());
+        }
+        let mut history = Self {
+            nodes,
+            roots,
+            active_root,
+            path: Vec::new(),
+            items: Vec::new(),
+        };
+        history.rebuild();
+        Ok(history)
+    }
+}
+
+impl From<ChatHistory> for ChatHistoryData {
+    fn from(history: ChatHistory) -> Self {
+        Self {
+            nodes: history.nodes,
+            roots: history.roots,
+            active_root: history.active_root,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(text: &str) -> ChatHistoryItem {
+        ChatData::user(text.to_string()).into()
+    }
+
+    fn model(text: &str) -> ChatHistoryItem {
+        ChatData::model(text.to_string()).into()
+    }
+
+    fn history(items: &[ChatHistoryItem]) -> ChatHistory {
+        let mut history = ChatHistory::default();
+        for item in items {
+            history.push(item.clone());
+        }
+        history
+    }
+
+    fn texts(history: &ChatHistory) -> Vec<&str> {
+        history
+            .iter()
+            .map(|item| match item {
+                ChatHistoryItem::Chat(chat) => chat.text.as_str(),
+                _ => "",
+            })
+            .collect()
+    }
+
+    #[test]
+    fn pushing_after_truncating_starts_a_branch() {
+        let mut history = history(&[user("hi"), model("hello")]);
+        history.truncate(1);
+        history.push(model("hey"));
+
+        assert_eq!(texts(&history), ["hi", "hey"]);
+        assert_eq!(
+            history.branch_position(1),
+            Some(BranchPosition { index: 1, count: 2 })
+        );
+        assert_eq!(
+            history.branch_position(0),
+            Some(BranchPosition { index: 0, count: 1 })
+        );
+    }
+
+    #[test]
+    fn editing_the_first_message_starts_a_new_root() {
+        let mut history = history(&[user("hi"), model("hello")]);
+        history.truncate(0);
+        history.push(user("bye"));
+
+        assert_eq!(texts(&history), ["bye"]);
+        assert_eq!(
+            history.branch_position(0),
+            Some(BranchPosition { index: 1, count: 2 })
+        );
+    }
+
+    #[test]
+    fn switching_branches_follows_the_active_children() {
+        let mut history = history(&[user("hi"), model("hello"), user("how are you?")]);
+        history.truncate(1);
+        history.push(model("hey"));
+
+        assert!(history.switch_branch(1, -1));
+        assert_eq!(texts(&history), ["hi", "hello", "how are you?"]);
+        assert!(history.switch_branch(1, 1));
+        assert_eq!(texts(&history), ["hi", "hey"]);
+    }
+
+    #[test]
+    fn switching_past_the_alternatives_changes_nothing() {
+        let mut history = history(&[user("hi"), model("hello")]);
+        history.truncate(1);
+        history.push(model("hey"));
+
+        assert!(!history.switch_branch(1, 1));
+        assert!(!history.switch_branch(1, -2));
+        assert!(!history.switch_branch(5, -1));
+        assert_eq!(texts(&history), ["hi", "hey"]);
+    }
+
+    #[test]
+    fn truncating_keeps_the_dropped_items_until_replaced() {
+        let mut history = history(&[user("hi"), model("hello")]);
+        history.truncate(1);
+
+        assert_eq!(texts(&history), ["hi"]);
+        assert_eq!(history.nodes.len(), 2);
+        // Nothing replaced the dropped reply, so it is not an alternative yet.
+        assert_eq!(
+            history.branch_position(0),
+            Some(BranchPosition { index: 0, count: 1 })
+        );
+    }
+
+    #[test]
+    fn truncating_past_the_end_changes_nothing() {
+        let mut history = history(&[user("hi"), model("hello")]);
+        history.truncate(2);
+        history.truncate(10);
+
+        assert_eq!(texts(&history), ["hi", "hello"]);
+    }
+
+    #[test]
+    fn removing_an_item_reattaches_what_followed_it() {
+        let mut history = history(&[user("hi"), model("hello"), user("how are you?")]);
+        history.truncate(2);
+        history.push(user("bye"));
+
+        let removed = history.remove(1);
+
+        assert!(matches!(removed, Some(ChatHistoryItem::Chat(chat)) if chat.text == "hello"));
+        assert_eq!(texts(&history), ["hi", "bye"]);
+        assert_eq!(
+            history.branch_position(1),
+            Some(BranchPosition { index: 1, count: 2 })
+        );
+    }
+
+    #[test]
+    fn round_trips_every_branch() {
+        let mut history = history(&[user("hi"), model("hello")]);
+        history.truncate(1);
+        history.push(model("hey"));
+
+        let json = serde_json::to_string(&history).unwrap();
+        let mut loaded = serde_json::from_str::<ChatHistory>(&json).unwrap();
+
+        assert_eq!(texts(&loaded), ["hi", "hey"]);
+        assert!(loaded.switch_branch(1, -1));
+        assert_eq!(texts(&loaded), ["hi", "hello"]);
+    }
+
+    #[test]
+    fn rejects_a_plain_list_of_items() {
+        let json = serde_json::to_string(&[user("hi")]).unwrap();
+
+        assert!(serde_json::from_str::<ChatHistory>(&json).is_err());
+    }
+
+    #[test]
+    fn rejects_ids_out_of_range() {
+        let json = r#"{"nodes": [], "roots": [0], "active_root": 0}"#;
+
+        assert!(serde_json::from_str::<ChatHistory>(json).is_err());
+    }
+}
@@ -0,0 +1,374 @@
+use claude_api_response::{ClaudeApiResponse, ClaudeApiStreamResponse, ContentBlock, StreamDelta};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{APIKeyManager, cli_handler::CliHandler, configuration::Configuration};
+
+use anyhow::{Context, anyhow};
+
+use super::{
+    CLAUDE_PROVIDER, ChatData, ChatHistoryItem, ChatMemory, ChatRole, HistoryEntry, OnlineProvider,
+    OnlineProviderImpl, ProviderImpl, Result, TokenUsage, ToolCallData, ToolCallDelta,
+    history_entries, redact,
+};
+
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+
+#[derive(Debug, Clone)]
+pub struct ClaudeProvider {
+    provider: OnlineProvider,
+    http_client: reqwest::Client,
+    memory: ChatMemory,
+    max_tokens: u32,
+}
+
+impl ProviderImpl for ClaudeProvider {
+    fn provider_str() -> &'static str {
+        CLAUDE_PROVIDER
+    }
+
+    fn memory(&self) -> &ChatMemory {
+        &self.memory
+    }
+
+    fn memory_mut(&mut self) -> &mut ChatMemory {
+        &mut self.memory
+    }
+}
+
+impl OnlineProviderImpl for ClaudeProvider {
+    type ProviderApiResponse = ClaudeApiResponse;
+    type ProviderApiStreamResponse = ClaudeApiStreamResponse;
+
+    fn build_chat_url(&self) -> anyhow::Result<Url> {
+        reqwest::Url::parse(&self.provider.url)
+            .context("Failed to parse provider url")?
+            .join("v1/messages")
+            .context("Failed to build claude url.")
+    }
+
+    fn build_chat_stream_url(&self) -> anyhow::Result<Url> {
+        self.build_chat_url()
+    }
+
+    fn build_models_url(&self) -> anyhow::Result<Url> {
+        reqwest::Url::parse(&self.provider.url)
+            .context("Failed to parse provider url")?
+            .join("v1/models")
+            .context("Failed to build claude models url.")
+    }
+
+    fn build_chat_body(
+        &self,
+        prompt: impl Into<String>,
+        turn: &[ChatHistoryItem],
+        stream: bool,
+    ) -> serde_json::Value {
+        let mut turns: Vec<(&'static str, Vec<serde_json::Value>)> = Vec::new();
+        let new_chat = ChatData::user(prompt.into()).into();
+        let history = self.memory.history.iter().chain([&new_chat]).chain(turn);
+
+        // The Messages API requires user and assistant turns to alternate, so consecutive
+        // items from the same role (e.g. file uploads followed by a prompt) are merged.
+        for (index, entry) in history_entries(history).into_iter().enumerate() {
+            for (role, blocks) in Self::serialise_entry(index, entry) {
+                match turns.last_mut() {
+                    Some((last_role, last_blocks)) if *last_role == role => {
+                        last_blocks.extend(blocks)
+                    }
+                    _ => turns.push((role, blocks)),
+                }
+            }
+        }
+
+        let messages = turns
+            .into_iter()
+            .map(|(role, blocks)| json!({ "role": role, "content": blocks }))
+            .collect::<Vec<_>>();
+
+        let mut body = json!({
+            "model": self.provider.model,
+            "max_tokens": self.max_tokens,
+            "messages": messages,
+            "tools": self.build_tools(),
+            "stream": stream
+        });
+        if let Some(instructions) = &self.memory.system_prompt {
+            body["system"] = json!(instructions);
+        }
+        body
+    }
+
+    fn get_http_client(&self) -> &reqwest::Client {
+        &self.http_client
+    }
+
+    fn authorise_request(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        let request = request.header("anthropic-version", ANTHROPIC_VERSION);
+        match &self.provider.api_key {
+            Some(api_key) => redact::secret_header(request, "x-api-key", api_key),
+            None => request,
+        }
+    }
+
+    fn decode_llm_response(&self, response: ClaudeApiResponse) -> anyhow::Result<String> {
+        let text = response
+            .content
+            .into_iter()
+            .filter_map(|block| match block {
+                ContentBlock::Text { text } => Some(text),
+                ContentBlock::ToolUse { .. } | ContentBlock::Other => None,
+            })
+            .collect::<String>();
+        Ok(text)
+    }
+
+    fn decode_llm_stream_response(
+        &self,
+        response: Self::ProviderApiStreamResponse,
+    ) -> anyhow::Result<String> {
+        match response {
+            ClaudeApiStreamResponse::ContentBlockDelta {
+                delta: StreamDelta::TextDelta { text },
+                ..
+            } => Ok(text),
+            ClaudeApiStreamResponse::Error { error } => Err(anyhow!(
+                "Claude stream error ({}): {}",
+                error.error_type,
+                error.message
+            )),
+            // Message lifecycle events, pings and non-text deltas carry no output text.
+            _ => Ok(String::new()),
+        }
+    }
+
+    fn context_window(&self) -> Option<u64> {
+        self.provider.context_window
+    }
+
+    fn decode_usage(&self, response: &ClaudeApiResponse) -> Option<TokenUsage> {
+        let usage = response.usage.as_ref()?;
+        Some(TokenUsage {
+            prompt: usage.input_tokens,
+            response: usage.output_tokens,
+        })
+    }
+
+    /// The prompt count arrives with `message_start` and the response count with the closing
+    /// `message_delta`.
+    fn decode_stream_usage(&self, response: &ClaudeApiStreamResponse) -> Option<TokenUsage> {
+        let usage = match response {
+            ClaudeApiStreamResponse::MessageStart { message } => message.usage.as_ref()?,
+            ClaudeApiStreamResponse::MessageDelta { usage } => usage,
+            _ => return None,
+        };
+        Some(TokenUsage {
+            prompt: usage.input_tokens,
+            response: usage.output_tokens,
+        })
+    }
+
+    fn decode_tool_calls(&self, response: &ClaudeApiResponse) -> Vec<ToolCallData> {
+        response
+            .content
+            .iter()
+            .filter_map(|block| match block {
+                ContentBlock::ToolUse { id, name, input } => Some(ToolCallData {
+                    id: Some(id.clone()),
+                    ..ToolCallData::requested(name.clone(), input.clone(), None)
+                }),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// A `tool_use` block starts with the id and name of the call, and its input follows as
+    /// pieces of JSON in `input_json_delta`s.
+    fn decode_stream_tool_call_deltas(
+        &self,
+        response: &ClaudeApiStreamResponse,
+    ) -> Vec<ToolCallDelta> {
+        let delta = match response {
+            ClaudeApiStreamResponse::ContentBlockStart {
+                index,
+                content_block: ContentBlock::ToolUse { id, name, .. },
+            } => ToolCallDelta {
+                index: *index,
+                id: Some(id.clone()),
+                name: Some(name.clone()),
+                arguments: String::new(),
+            },
+            ClaudeApiStreamResponse::ContentBlockDelta {
+                index,
+                delta: StreamDelta::InputJsonDelta { partial_json },
+            } => ToolCallDelta {
+                index: *index,
+                arguments: partial_json.clone(),
+                ..Default::default()
+            },
+            _ => return Vec::new(),
+        };
+        vec![delta]
+    }
+}
+
+impl ClaudeProvider {
+    pub fn new(
+        config: &Configuration,
+        api_key_manager: &APIKeyManager,
+        cli_handler: Option<&CliHandler>,
+    ) -> Result<Self> {
+        Ok(Self {
+            provider: OnlineProvider::new(
+                ClaudeProvider::provider_str(),
+                &config.provider_opts.claude.online_opts,
+                api_key_manager,
+                cli_handler,
+            )?,
+            http_client: reqwest::Client::builder().build()?,
+            memory: ChatMemory::new(config),
+            max_tokens: config.provider_opts.claude.max_tokens,
+        })
+    }
+}
+
+impl ClaudeProvider {
+    /// Content blocks for one entry of the history, by role. Calls are `tool_use` blocks of
+    /// the assistant, answered by `tool_result` blocks of the user.
+    fn serialise_entry(
+        index: usize,
+        entry: HistoryEntry,
+    ) -> Vec<(&'static str, Vec<serde_json::Value>)> {
+        match entry {
+            HistoryEntry::Message { role, text } => {
+                let role = match role {
+                    ChatRole::Model => "assistant",
+                    _ => "user",
+                };
+                vec![(role, vec![json!({ "type": "text", "text": text })])]
+            }
+            HistoryEntry::ToolCalls { text, calls } => {
+                let text = text
+                    .filter(|text| !text.is_empty())
+                    .map(|text| json!({ "type": "text", "text": text }));
+                let tool_uses = calls.iter().enumerate().map(|(position, call)| {
+                    json!({
+                        "type": "tool_use",
+                        "id": call.id_or_position(index, position),
+                        "name": call.name,
+                        "input": call.arguments
+                    })
+                });
+                let tool_results = calls.iter().enumerate().map(|(position, call)| {
+                    json!({
+                        "type": "tool_result",
+                        "tool_use_id": call.id_or_position(index, position),
+                        "content": call.output,
+                        "is_error": call.failed
+                    })
+                });
+                vec![
+                    ("assistant", text.into_iter().chain(tool_uses).collect()),
+                    ("user", tool_results.collect()),
+                ]
+            }
+        }
+    }
+
+    fn build_tools(&self) -> serde_json::Value {
+        let mut enabled_tools = Vec::new();
+        if self.memory.tools.search {
+            enabled_tools.push(json!({
+                "type": "web_search_20250305",
+                "name": "web_search",
+            }));
+        }
+        enabled_tools.extend(self.memory.enabled_tools().map(|tool| {
+            json!({
+                "name": tool.name(),
+                "description": tool.description(),
+                "input_schema": tool.parameters()
+            })
+        }));
+        json!(enabled_tools)
+    }
+}
+
+mod claude_api_response {
+    use super::*;
+
+    #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct ClaudeApiResponse {
+        pub content: Vec<ContentBlock>,
+        pub usage: Option<Usage>,
+    }
+
+    #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct Usage {
+        pub input_tokens: Option<u64>,
+        pub output_tokens: Option<u64>,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    #[serde(tag = "type", rename_all = "snake_case")]
+    pub enum ContentBlock {
+        Text {
+            text: String,
+        },
+        ToolUse {
+            id: String,
+            name: String,
+            #[serde(default)]
+            input: serde_json::Value,
+        },
+        #[serde(other)]
+        Other,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    #[serde(tag = "type", rename_all = "snake_case")]
+    pub enum ClaudeApiStreamResponse {
+        MessageStart {
+            message: ClaudeApiResponse,
+        },
+        MessageDelta {
+            usage: Usage,
+        },
+        ContentBlockStart {
+            #[serde(default)]
+            index: usize,
+            content_block: ContentBlock,
+        },
+        ContentBlockDelta {
+            #[serde(default)]
+            index: usize,
+            delta: StreamDelta,
+        },
+        Error {
+            error: StreamError,
+        },
+        #[serde(other)]
+        Other,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    #[serde(tag = "type", rename_all = "snake_case")]
+    pub enum StreamDelta {
+        TextDelta {
+            text: String,
+        },
+        InputJsonDelta {
+            partial_json: String,
+        },
+        #[serde(other)]
+        Other,
+    }
+
+    #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct StreamError {
+        #[serde(rename = "type")]
+        pub error_type: String,
+        pub message: String,
+    }
+}
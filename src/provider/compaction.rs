@@ -0,0 +1,327 @@
+use crate::configuration::CompactionStrategy;
+
+use super::{ChatData, ChatHistory, ChatHistoryItem, ChatMemory, ChatRole, tokens};
+
+/// Room kept for the summary written by the `summarise` strategy.
+const SUMMARY_TOKENS: u64 = 1024;
+
+/// Messages left out of a conversation so it fits the context window, and the context message
+/// that takes their place.
+#[derive(Debug, Clone)]
+pub(crate) struct Compaction {
+    /// Indices on the active branch, in ascending order.
+    removed: Vec<usize>,
+    context: ChatData,
+    /// Messages left out, not counting context messages from earlier compactions.
+    messages: usize,
+    summarised: bool,
+}
+
+impl Compaction {
+    /// Leave out the messages at `removed`, noting that they were.
+    pub(super) fn dropped(history: &ChatHistory, removed: Vec<usize>) -> Self {
+        Self {
+            messages: count_messages(history, &removed),
+            removed,
+            context: ChatData::context(
+                "Earlier messages were left out to fit the context window.".to_string(),
+            ),
+            summarised: false,
+        }
+    }
+
+    /// Replace the messages at `removed` with a summary of them.
+    pub(super) fn summarised(history: &ChatHistory, removed: Vec<usize>, summary: &str) -> Self {
+        Self {
+            messages: count_messages(history, &removed),
+            removed,
+            context: ChatData::context(format!(
+                "Summary of the earlier conversation:\n\n{}",
+                summary.trim()
+            )),
+            summarised: true,
+        }
+    }
+
+    /// Apply to a history whose active branch starts with the one the compaction was planned
+    /// for, e.g. the TUI's copy that also holds the prompt being answered.
+    pub(crate) fn apply(&self, history: &mut ChatHistory) {
+        let Some((first, rest)) = self.removed.split_first() else {
+            return;
+        };
+        for index in rest.iter().rev() {
+            history.remove(*index);
+        }
+        history.replace(*first, self.context.clone().into());
+    }
+
+    /// One line describing the compaction.
+    pub(crate) fn summary(&self) -> String {
+        let action = match self.summarised {
+            true => "Summarised",
+            false => "Left out",
+        };
+        format!(
+            "{action} {} earlier messages to fit the context window.",
+            self.messages
+        )
+    }
+}
+
+/// Tokens a request may use before the conversation is compacted: `threshold` of the context
+/// window, rounded down.
+pub(super) fn budget(window: u64, threshold: f64) -> u64 {
+    (window as f64 * threshold) as u64
+}
+
+/// Indices of the messages to leave out so a request for `prompt` fits in `budget` tokens,
+/// oldest first. Empty when it already fits.
+pub(super) fn plan(
+    memory: &ChatMemory,
+    prompt: &str,
+    strategy: CompactionStrategy,
+    budget: u64,
+) -> Vec<usize> {
+    let budget = match strategy {
+        CompactionStrategy::Off => return Vec::new(),
+        CompactionStrategy::Summarise => budget.saturating_sub(SUMMARY_TOKENS),
+        CompactionStrategy::DropOldest | CompactionStrategy::KeepFiles => budget,
+    };
+    let sizes = memory
+        .history
+        .iter()
+        .map(tokens::estimate_item_tokens)
+        .collect::<Vec<_>>();
+    let mut total = memory
+        .system_prompt
+        .as_deref()
+        .map(tokens::estimate_tokens)
+        .unwrap_or_default()
+        + tokens::estimate_tokens(prompt)
+        + sizes.iter().sum::<u64>();
+    if total <= budget {
+        return Vec::new();
+    }
+
+    let mut candidates = memory
+        .history
+        .iter()
+        .enumerate()
+        .filter(|(_, item)| {
+            strategy == CompactionStrategy::DropOldest
+                || !matches!(item, ChatHistoryItem::FileUpload(_))
+        })
+        .peekable();
+    let mut removed = Vec::new();
+    while let Some((index, _)) = candidates.next() {
+        removed.push(index);
+        total -= sizes[index];
+        // Only stop at the end of a turn, so no reply is kept without the message it answers.
+        let ends_turn = !matches!(
+            candidates.peek(),
+            Some((_, ChatHistoryItem::ToolCall(_)))
+                | Some((
+                    _,
+                    ChatHistoryItem::Chat(ChatData {
+                        role: ChatRole::Model,
+                        ..
+                    })
+                ))
+        );
+        if total <= budget && ends_turn {
+            break;
+        }
+    }
+    removed
+}
+
+/// Prompt asking the model to summarise the messages at `removed`.
+pub(super) fn summary_prompt(history: &ChatHistory, removed: &[usize]) -> String {
+    let transcript = removed
+        .iter()
+        .map(|index| match &history[*index] {
+            ChatHistoryItem::Chat(chat) => format!("{}: {}", chat.role.display(), chat.text),
+            ChatHistoryItem::FileUpload(file) => {
+                format!("File {}:\n{}", file.relative_filepath, file.text)
+            }
+            ChatHistoryItem::ToolCall(call) => {
+                format!("Tool {}({}): {}", call.name, call.arguments, call.output)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n");
+    format!(
+        "Summarise the conversation below in at most a few paragraphs, keeping the decisions, \
+        facts and open questions needed to continue it. Reply with the summary only.\n\n{transcript}"
+    )
+}
+
+fn count_messages(history: &ChatHistory, indices: &[usize]) -> usize {
+    indices
+        .iter()
+        .filter(|index| {
+            !matches!(&history[**index], ChatHistoryItem::Chat(chat) if chat.role == ChatRole::Context)
+        })
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::{FileUploadData, ToolCallData};
+
+    /// Text estimated at `tokens` tokens.
+    fn text(tokens: usize) -> String {
+        "abcd".repeat(tokens)
+    }
+
+    fn user(tokens: usize) -> ChatHistoryItem {
+        ChatData::user(text(tokens)).into()
+    }
+
+    fn model(tokens: usize) -> ChatHistoryItem {
+        ChatData::model(text(tokens)).into()
+    }
+
+    fn file(tokens: usize) -> ChatHistoryItem {
+        FileUploadData {
+            text: text(tokens),
+            relative_filepath: "notes.md".to_string(),
+        }
+        .into()
+    }
+
+    fn tool_call(tokens: usize) -> ChatHistoryItem {
+        ToolCallData {
+            output: text(tokens),
+            ..ToolCallData::requested("current_time".to_string(), serde_json::json!({}), None)
+        }
+        .into()
+    }
+
+    /// A conversation with a 10 token system prompt.
+    fn memory(items: Vec<ChatHistoryItem>) -> ChatMemory {
+        let mut history = ChatHistory::default();
+        for item in items {
+            history.push(item);
+        }
+        ChatMemory {
+            history,
+            system_prompt: Some(text(10)),
+            tools: Default::default(),
+            usage: Default::default(),
+            toolbox: Default::default(),
+        }
+    }
+
+    #[test]
+    fn budget_is_the_threshold_share_of_the_window_rounded_down() {
+        assert_eq!(budget(1000, 0.8), 800);
+        assert_eq!(budget(1001, 0.5), 500);
+        assert_eq!(budget(1000, 1.0), 1000);
+        assert_eq!(budget(1000, 0.0), 0);
+    }
+
+    #[test]
+    fn keeps_everything_that_fits_the_budget_exactly() {
+        // 10 system + 10 prompt + 20 history.
+        let memory = memory(vec![user(10), model(10)]);
+
+        for strategy in [
+            CompactionStrategy::DropOldest,
+            CompactionStrategy::KeepFiles,
+        ] {
+            assert!(plan(&memory, &text(10), strategy, 40).is_empty());
+            assert_eq!(plan(&memory, &text(10), strategy, 39), [0, 1]);
+        }
+    }
+
+    #[test]
+    fn off_never_drops_anything() {
+        let memory = memory(vec![user(100), model(100)]);
+
+        assert!(plan(&memory, &text(10), CompactionStrategy::Off, 0).is_empty());
+    }
+
+    #[test]
+    fn drop_oldest_drops_files_and_whole_turns() {
+        // 10 system + 10 prompt + 50 history.
+        let memory = memory(vec![file(10), user(10), model(10), user(10), model(10)]);
+
+        let plan_for = |budget| plan(&memory, &text(10), CompactionStrategy::DropOldest, budget);
+        assert!(plan_for(70).is_empty());
+        assert_eq!(plan_for(69), [0]);
+        assert_eq!(plan_for(60), [0]);
+        // Dropping the question alone would fit, but its answer goes with it.
+        assert_eq!(plan_for(59), [0, 1, 2]);
+    }
+
+    #[test]
+    fn keep_files_only_drops_messages() {
+        let memory = memory(vec![file(10), user(10), model(10), user(10), model(10)]);
+
+        let plan_for = |budget| plan(&memory, &text(10), CompactionStrategy::KeepFiles, budget);
+        assert!(plan_for(70).is_empty());
+        assert_eq!(plan_for(69), [1, 2]);
+        assert_eq!(plan_for(50), [1, 2]);
+        assert_eq!(plan_for(49), [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn summarise_leaves_room_for_the_summary_and_keeps_files() {
+        let memory = memory(vec![file(10), user(10), model(10), user(10), model(10)]);
+
+        let plan_for = |budget| {
+            plan(
+                &memory,
+                &text(10),
+                CompactionStrategy::Summarise,
+                SUMMARY_TOKENS + budget,
+            )
+        };
+        assert!(plan_for(70).is_empty());
+        assert_eq!(plan_for(69), [1, 2]);
+        assert_eq!(plan_for(50), [1, 2]);
+        assert_eq!(plan_for(49), [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn tool_calls_stay_with_the_turn_that_made_them() {
+        let memory = memory(vec![
+            user(10),
+            tool_call(10),
+            model(10),
+            user(10),
+            model(10),
+        ]);
+
+        assert_eq!(
+            plan(&memory, &text(10), CompactionStrategy::DropOldest, 69),
+            [0, 1, 2]
+        );
+    }
+
+    #[test]
+    fn never_drops_the_prompt_being_answered() {
+        let memory = memory(vec![user(10), model(10), user(10), model(10)]);
+
+        // The prompt alone is over budget, so every earlier message goes, but not the prompt.
+        for strategy in [
+            CompactionStrategy::DropOldest,
+            CompactionStrategy::KeepFiles,
+            CompactionStrategy::Summarise,
+        ] {
+            let removed = plan(&memory, &text(100), strategy, 50);
+            assert_eq!(removed, [0, 1, 2, 3]);
+
+            let mut with_prompt = memory.history.clone();
+            with_prompt.push(ChatData::user(text(100)).into());
+            Compaction::dropped(&memory.history, removed).apply(&mut with_prompt);
+            assert_eq!(with_prompt.len(), 2);
+            assert!(matches!(
+                &with_prompt[1],
+                ChatHistoryItem::Chat(chat) if chat.role == ChatRole::User && chat.text == text(100)
+            ));
+        }
+    }
+}
@@ -1,9 +1,50 @@
+use std::path::PathBuf;
+
 pub type Result<T> = std::result::Result<T, Error>;
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
-    #[error("Fetching api key for provider. {0}")]
-    KeyFetchError(#[from] keyring::Error),
-    #[error("No api key stored")]
-    NoApiKey,
+    #[error(
+        "Failed to access the system keychain for provider {provider:?}. \
+        Check that a Secret Service (e.g. gnome-keyring) is running."
+    )]
+    Keychain {
+        provider: String,
+        #[source]
+        source: keyring::Error,
+    },
+    #[error(
+        "No api key found for provider {provider:?}. Set {env_var}, add it to the credentials \
+        file, add one with `llcli_rs api-key set`, or run without --quiet to be prompted."
+    )]
+    NoApiKey { provider: String, env_var: String },
+    #[error("Credentials file {path:?} is readable by other users. Restrict it with `chmod 600`.")]
+    InsecureCredentialsFile { path: PathBuf },
+    #[error("Failed to read credentials file {path:?}.")]
+    CredentialsFile {
+        path: PathBuf,
+        #[source]
+        source: config::ConfigError,
+    },
+    #[error("Api key command for provider {provider:?} failed. {reason}")]
+    KeyCommand { provider: String, reason: String },
+    #[error("Failed to read api key for provider {provider:?}.")]
+    KeyPrompt {
+        provider: String,
+        #[source]
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+    #[error("Unknown provider {name:?}. Available providers: {available}")]
+    UnknownProvider { name: String, available: String },
+    #[error(
+        "Provider {name:?} in openai_compatible has the name of a built in provider. Rename it."
+    )]
+    ProviderNameTaken { name: String },
+    #[error("Network request to the LLM provider failed.")]
+    Network(#[from] reqwest::Error),
+    #[error("LLM provider responded with {status}. {body}")]
+    Api {
+        status: reqwest::StatusCode,
+        body: String,
+    },
 }
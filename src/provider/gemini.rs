@@ -1,4 +1,4 @@
-use gemini_api_response::GeminiApiResponse;
+use gemini_api_response::{CountTokensResponse, GeminiApiResponse};
 use reqwest::Url;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
@@ -8,18 +8,18 @@ use crate::{APIKeyManager, cli_handler::CliHandler, configuration::Configuration
 use anyhow::Context;
 
 use super::{
-    ChatData, ChatHistoryItem, ChatRole, FileUploadData, GEMINI_PROVIDER, LLMTools, OnlineProvider,
-    OnlineProviderImpl, ProviderImpl,
+    ChatData, ChatHistoryItem, ChatMemory, ChatRole, GEMINI_PROVIDER, HistoryEntry, OnlineProvider,
+    OnlineProviderImpl, ProviderImpl, Result, TokenCount, TokenUsage, ToolCallData, ensure_success,
+    history_entries, redact,
 };
 
+const API_KEY_HEADER: &str = "x-goog-api-key";
+
 #[derive(Debug, Clone)]
 pub struct GeminiProvider {
     provider: OnlineProvider,
     http_client: reqwest::Client,
-    memory: Vec<ChatHistoryItem>,
-    system_prompt: Option<String>,
-
-    gemini_tools: LLMTools,
+    memory: ChatMemory,
 }
 
 impl ProviderImpl for GeminiProvider {
@@ -27,70 +27,12 @@ impl ProviderImpl for GeminiProvider {
         GEMINI_PROVIDER
     }
 
-    fn merge_tools(&mut self, tools: LLMTools) {
-        self.gemini_tools.merge(&tools);
-    }
-
-    fn flags_mut(&mut self) -> &mut LLMTools {
-        &mut self.gemini_tools
-    }
-
-    fn flags(&self) -> &LLMTools {
-        &self.gemini_tools
-    }
-
-    fn update_memory(&mut self, prompt: String, response: String) -> anyhow::Result<()> {
-        self.memory.extend(vec![
-            ChatData {
-                role: ChatRole::User,
-                text: prompt,
-            }
-            .into(),
-            ChatData {
-                role: ChatRole::Model,
-                text: response,
-            }
-            .into(),
-        ]);
-
-        Ok(())
-    }
-
-    /// Used when providing model context.
-    fn add_chat_to_context(&mut self, chat: ChatHistoryItem) -> anyhow::Result<Option<usize>> {
-        match chat {
-            ChatHistoryItem::Chat(ChatData {
-                role: ChatRole::System,
-                text,
-            }) => {
-                self.system_prompt = Some(text);
-                Ok(None)
-            }
-            _ => {
-                self.memory.push(chat);
-                Ok(Some(self.memory.len() - 1))
-            }
-        }
-    }
-
-    fn append_chat_in_context(&mut self, index: usize, text: &str) -> anyhow::Result<()> {
-        let existing = self
-            .memory
-            .get_mut(index)
-            .context(format!("Failed to get chat at index {}", index))?;
-        match existing {
-            ChatHistoryItem::FileUpload(file) => {}
-            ChatHistoryItem::Chat(chat) => chat.text.push_str(text),
-        }
-        Ok(())
-    }
-
-    fn clear_memory(&mut self) -> anyhow::Result<()> {
-        Ok(self.memory.clear())
+    fn memory(&self) -> &ChatMemory {
+        &self.memory
     }
 
-    fn get_history(&self) -> &Vec<ChatHistoryItem> {
-        &self.memory
+    fn memory_mut(&mut self) -> &mut ChatMemory {
+        &mut self.memory
     }
 }
 
@@ -99,37 +41,29 @@ impl OnlineProviderImpl for GeminiProvider {
     type ProviderApiStreamResponse = GeminiApiResponse;
 
     fn build_chat_url(&self) -> anyhow::Result<Url> {
-        let mut url = reqwest::Url::parse(&self.provider.url)
-            .context("Failed to parse provider url")?
-            .join("v1beta/models/")
-            .context("Failed to build gemini url.")?
-            .join(&self.provider.model)
-            .context("Failed to build gemini model url")?;
+        self.build_model_method_url("generateContent")
+    }
 
-        url = Url::parse(&(url.to_string() + ":generateContent"))
-            .context("Failed to append chat generation type")?;
-        url.set_query(Some(format!("key={}", &self.provider.api_key).as_str()));
+    fn build_chat_stream_url(&self) -> anyhow::Result<reqwest::Url> {
+        let mut url = self.build_model_method_url("streamGenerateContent")?;
+        url.query_pairs_mut().append_pair("alt", "sse");
         Ok(url)
     }
 
-    fn build_chat_stream_url(&self) -> anyhow::Result<reqwest::Url> {
-        let mut url = reqwest::Url::parse(&self.provider.url)
+    fn build_models_url(&self) -> anyhow::Result<Url> {
+        reqwest::Url::parse(&self.provider.url)
             .context("Failed to parse provider url")?
-            .join("v1beta/models/")
-            .context("Failed to build gemini url.")?
-            .join(&self.provider.model)
-            .context("Failed to build gemini model url")?;
-
-        url = Url::parse(&(url.to_string() + ":streamGenerateContent"))
-            .context("Failed to append chat generation type")?;
-        url.set_query(Some(
-            format!("key={}&alt=sse", &self.provider.api_key).as_str(),
-        ));
-        Ok(url)
+            .join("v1beta/models")
+            .context("Failed to build gemini models url.")
     }
 
-    fn build_chat_body(&self, prompt: impl Into<String>) -> serde_json::Value {
-        let system_prompt = if let Some(instructions) = &self.system_prompt {
+    fn build_chat_body(
+        &self,
+        prompt: impl Into<String>,
+        turn: &[ChatHistoryItem],
+        _stream: bool,
+    ) -> serde_json::Value {
+        let system_prompt = if let Some(instructions) = &self.memory.system_prompt {
             json!({
                 "parts": [
                     {
@@ -140,25 +74,9 @@ impl OnlineProviderImpl for GeminiProvider {
         } else {
             json!(null)
         };
-        let mut temp_chat_hist = self
-            .memory
-            .iter()
-            .filter(|item| match item {
-                ChatHistoryItem::Chat(ChatData {
-                    role: ChatRole::System,
-                    text: _,
-                }) => false,
-                _ => true,
-            })
-            .map(|chat| Self::serialise_chat(chat))
-            .collect::<Vec<_>>();
-
-        let new_chat = ChatData {
-            role: ChatRole::User,
-            text: prompt.into(),
-        }
-        .into();
-        temp_chat_hist.push(Self::serialise_chat(&new_chat));
+        let new_chat = ChatData::user(prompt.into()).into();
+        let temp_chat_hist =
+            Self::serialise_history(self.memory.history.iter().chain([&new_chat]).chain(turn));
 
         json!({
             "system_instruction": system_prompt,
@@ -171,17 +89,25 @@ impl OnlineProviderImpl for GeminiProvider {
         &self.http_client
     }
 
+    fn authorise_request(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        match &self.provider.api_key {
+            Some(api_key) => redact::secret_header(request, API_KEY_HEADER, api_key),
+            None => request,
+        }
+    }
+
     fn decode_llm_response(&self, response: GeminiApiResponse) -> anyhow::Result<String> {
+        // A response that only calls tools has no text.
         let text = response
             .candidates
             .first()
             .context("Gemini response has no candidate responses")?
             .content
             .parts
-            .last()
-            .context("Failed to extract last llm response")?
-            .text
-            .clone();
+            .iter()
+            .filter_map(|part| part.text.clone())
+            .next_back()
+            .unwrap_or_default();
         Ok(text)
     }
 
@@ -191,6 +117,66 @@ impl OnlineProviderImpl for GeminiProvider {
     ) -> anyhow::Result<String> {
         self.decode_llm_response(response)
     }
+
+    fn context_window(&self) -> Option<u64> {
+        self.provider.context_window
+    }
+
+    fn decode_usage(&self, response: &GeminiApiResponse) -> Option<TokenUsage> {
+        let usage = response.usage_metadata.as_ref()?;
+        Some(TokenUsage {
+            prompt: usage.prompt_token_count,
+            response: usage.candidates_token_count,
+        })
+    }
+
+    fn decode_stream_usage(&self, response: &GeminiApiResponse) -> Option<TokenUsage> {
+        // Every chunk carries the running totals, so the last one seen is the final count.
+        self.decode_usage(response)
+    }
+
+    fn decode_tool_calls(&self, response: &GeminiApiResponse) -> Vec<ToolCallData> {
+        response
+            .candidates
+            .first()
+            .into_iter()
+            .flat_map(|candidate| &candidate.content.parts)
+            .filter_map(|part| {
+                let call = part.function_call.as_ref()?;
+                Some(ToolCallData::requested(
+                    call.name.clone(),
+                    call.args.clone(),
+                    part.thought_signature.clone(),
+                ))
+            })
+            .collect()
+    }
+
+    fn decode_stream_tool_calls(&self, response: &GeminiApiResponse) -> Vec<ToolCallData> {
+        self.decode_tool_calls(response)
+    }
+
+    async fn count_tokens(&self, prompt: String) -> anyhow::Result<TokenCount> {
+        let mut request = self.build_chat_body(prompt, &[], false);
+        request["model"] = json!(format!("models/{}", self.provider.model));
+        let request = self
+            .get_http_client()
+            .post(self.build_model_method_url("countTokens")?)
+            .json(&json!({ "generateContentRequest": request }));
+        let response = self
+            .send_request(request)
+            .await
+            .context("Request failed to LLM Provider.")?;
+        let response = ensure_success(response)
+            .await?
+            .json::<CountTokensResponse>()
+            .await
+            .context("Failed to decode Gemini token count.")?;
+        Ok(TokenCount {
+            tokens: response.total_tokens,
+            estimated: false,
+        })
+    }
 }
 
 impl GeminiProvider {
@@ -198,66 +184,157 @@ impl GeminiProvider {
         config: &Configuration,
         api_key_manager: &APIKeyManager,
         cli_handler: Option<&CliHandler>,
-    ) -> Self {
-        Self {
+    ) -> Result<Self> {
+        Ok(Self {
             provider: OnlineProvider::new(
                 GeminiProvider::provider_str(),
                 &config.provider_opts.gemini.online_opts,
                 api_key_manager,
                 cli_handler,
-            ),
-            http_client: reqwest::Client::builder()
-                .build()
-                .expect("Failed to build http client."),
-            memory: Vec::new(),
-            gemini_tools: LLMTools::new(config),
-            system_prompt: None,
-        }
+            )?,
+            http_client: reqwest::Client::builder().build()?,
+            memory: ChatMemory::new(config),
+        })
     }
 }
 
 impl GeminiProvider {
-    fn serialise_chat(chat: &ChatHistoryItem) -> serde_json::Value {
-        match chat {
-            ChatHistoryItem::Chat(chat) => {
-                let role = match chat.role {
-                    ChatRole::Model => "model",
-                    ChatRole::System => "system_instruction",
-                    ChatRole::User => "user",
-                };
-
-                json!({
-                    "role": role,
-                    "parts": [
-                        {
-                            "text": chat.text
-                        }
-                    ]
-                })
+    /// Url for a method on the configured model, e.g. `models/<model>:generateContent`.
+    fn build_model_method_url(&self, method: &str) -> anyhow::Result<Url> {
+        let url = reqwest::Url::parse(&self.provider.url)
+            .context("Failed to parse provider url")?
+            .join("v1beta/models/")
+            .context("Failed to build gemini url.")?
+            .join(&self.provider.model)
+            .context("Failed to build gemini model url")?;
+
+        Url::parse(&format!("{url}:{method}")).context("Failed to append gemini method")
+    }
+
+    /// Contents for `history`. The calls made in one response go in a single model content,
+    /// after any text the model wrote with them, and their responses in the next user content.
+    fn serialise_history<'a>(
+        history: impl IntoIterator<Item = &'a ChatHistoryItem>,
+    ) -> Vec<serde_json::Value> {
+        let mut contents = Vec::new();
+        for entry in history_entries(history) {
+            match entry {
+                HistoryEntry::Message { role, text } => {
+                    contents.push(Self::serialise_message(role, &text))
+                }
+                HistoryEntry::ToolCalls { text, calls } => {
+                    let (function_calls, function_responses): (Vec<_>, Vec<_>) =
+                        calls.into_iter().map(Self::serialise_tool_call).unzip();
+                    let text = text.map(|text| json!({ "text": text }));
+                    contents.push(json!({
+                        "role": "model",
+                        "parts": text.into_iter().chain(function_calls).collect::<Vec<_>>()
+                    }));
+                    contents.push(json!({ "role": "user", "parts": function_responses }));
+                }
             }
-            ChatHistoryItem::FileUpload(file) => {
-                let role = ChatRole::User;
-                json!({
-                    "role": role,
-                    "parts": [
-                        {
-                            "text": format!("## <{}> Contents below ##\n{}",file.relative_filepath, file.text)
-                        }
-                    ]
-                })
+        }
+        contents
+    }
+
+    fn serialise_message(role: ChatRole, text: &str) -> serde_json::Value {
+        let role = match role {
+            ChatRole::Model => "model",
+            _ => "user",
+        };
+
+        json!({
+            "role": role,
+            "parts": [
+                {
+                    "text": text
+                }
+            ]
+        })
+    }
+
+    /// The model's `functionCall` part and the `functionResponse` part answering it.
+    fn serialise_tool_call(call: &ToolCallData) -> (serde_json::Value, serde_json::Value) {
+        let mut function_call = json!({
+            "functionCall": {
+                "name": call.name,
+                "args": call.arguments
             }
+        });
+        if let Some(signature) = &call.signature {
+            function_call["thoughtSignature"] = json!(signature);
         }
+        let response = match call.failed {
+            true => json!({ "error": call.output }),
+            false => json!({ "output": call.output }),
+        };
+        let function_response = json!({
+            "functionResponse": {
+                "name": call.name,
+                "response": response
+            }
+        });
+        (function_call, function_response)
     }
 
     fn build_tools(&self) -> serde_json::Value {
         let mut enabled_tools = Vec::new();
-        if self.gemini_tools.search {
+        if self.memory.tools.search {
             enabled_tools.push(json!({ "google_search": {}}));
         }
+        let declarations = self
+            .memory
+            .enabled_tools()
+            .map(|tool| {
+                let mut declaration = json!({
+                    "name": tool.name(),
+                    "description": tool.description(),
+                });
+                // Gemini rejects object schemas without properties, so tools without
+                // arguments leave the schema out.
+                let parameters = gemini_schema(tool.parameters());
+                if parameters["properties"]
+                    .as_object()
+                    .is_some_and(|properties| !properties.is_empty())
+                {
+                    declaration["parameters"] = parameters;
+                }
+                declaration
+            })
+            .collect::<Vec<_>>();
+        if !declarations.is_empty() {
+            enabled_tools.push(json!({ "function_declarations": declarations }));
+        }
         json!(enabled_tools)
     }
 }
 
+/// Drop the JSON schema keywords Gemini rejects, such as `$schema` and `additionalProperties`,
+/// which schemas from MCP servers often carry.
+fn gemini_schema(schema: serde_json::Value) -> serde_json::Value {
+    let serde_json::Value::Object(schema) = schema else {
+        return schema;
+    };
+    schema
+        .into_iter()
+        .filter(|(key, _)| !key.starts_with('$') && key != "additionalProperties")
+        .map(|(key, value)| {
+            let value = match (key.as_str(), value) {
+                ("properties", serde_json::Value::Object(properties)) => properties
+                    .into_iter()
+                    .map(|(name, property)| (name, gemini_schema(property)))
+                    .collect(),
+                ("items", items) => gemini_schema(items),
+                ("anyOf", serde_json::Value::Array(schemas)) => {
+                    schemas.into_iter().map(gemini_schema).collect()
+                }
+                (_, value) => value,
+            };
+            (key, value)
+        })
+        .collect()
+}
+
 mod gemini_api_response {
     use super::*;
 
@@ -265,6 +342,20 @@ mod gemini_api_response {
     #[serde(rename_all = "camelCase")]
     pub struct GeminiApiResponse {
         pub candidates: Vec<Candidate>,
+        pub usage_metadata: Option<UsageMetadata>,
+    }
+
+    #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct UsageMetadata {
+        pub prompt_token_count: Option<u64>,
+        pub candidates_token_count: Option<u64>,
+    }
+
+    #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct CountTokensResponse {
+        pub total_tokens: u64,
     }
 
     #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -282,6 +373,16 @@ mod gemini_api_response {
     #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
     #[serde(rename_all = "camelCase")]
     pub struct Part {
-        pub text: String,
+        pub text: Option<String>,
+        pub function_call: Option<FunctionCall>,
+        pub thought_signature: Option<String>,
+    }
+
+    #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct FunctionCall {
+        pub name: String,
+        #[serde(default)]
+        pub args: serde_json::Value,
     }
 }
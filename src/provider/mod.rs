@@ -1,25 +1,57 @@
 mod api_key_manager;
-mod error;
+mod chat_history;
+mod claude;
+mod compaction;
+pub(crate) mod error;
 mod gemini;
+mod ollama;
+mod openai;
+mod redact;
+mod registry;
+mod tokens;
+
+use std::borrow::Cow;
+use std::collections::{BTreeSet, VecDeque};
+use std::sync::Arc;
 
 use anyhow::Context;
-pub use api_key_manager::APIKeyManager;
+pub use api_key_manager::{APIKeyManager, env_var_name};
+pub(crate) use chat_history::ChatHistory;
+pub(crate) use compaction::Compaction;
 use derive_more::From;
-use eventsource_stream::Eventsource;
-use futures_util::{Stream, StreamExt};
-use gemini::GeminiProvider;
+use eventsource_stream::{EventStreamError, Eventsource};
+use futures_util::future::BoxFuture;
+use futures_util::stream::{self, BoxStream};
+use futures_util::{FutureExt, Stream, StreamExt, TryFutureExt, future};
+pub use registry::ProviderRegistry;
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+pub(crate) use tokens::TokenUsage;
+pub use tokens::{TokenCount, TokenReport, format_count};
+use tracing::trace;
 
-use crate::configuration::Configuration;
+use crate::configuration::{CompactionOptions, CompactionStrategy, Configuration};
+use crate::tools::{Approver, Tool, Toolbox};
 use crate::{cli_handler::CliHandler, configuration::OnlineProviderOpts};
 use error::{Error, Result};
 
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 struct OnlineProvider {
-    api_key: String,
+    api_key: Option<String>,
     url: String,
     model: String,
+    context_window: Option<u64>,
+}
+
+impl std::fmt::Debug for OnlineProvider {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OnlineProvider")
+            .field("api_key", &self.api_key.as_ref().map(|_| "REDACTED"))
+            .field("url", &self.url)
+            .field("model", &self.model)
+            .field("context_window", &self.context_window)
+            .finish()
+    }
 }
 
 impl OnlineProvider {
@@ -28,22 +60,88 @@ impl OnlineProvider {
         config: &OnlineProviderOpts,
         api_key_manager: &APIKeyManager,
         cli_handler: Option<&CliHandler>,
-    ) -> Self {
-        let api_key_result = api_key_manager.fetch_api_key(provider);
-        let api_key = match (api_key_result, cli_handler) {
-            (Ok(key), _) => key,
-            (Err(Error::NoApiKey), Some(cli_handler)) => {
-                cli_handler.get_api_key().expect("Failed to get api key")
-            }
-            (Err(Error::NoApiKey), None) => panic!("No api key found. Please add an api key."),
-            (Err(Error::KeyFetchError(err)), _) => {
-                panic!("Error fetching key from keychain. {:?}", err)
-            }
-        };
+    ) -> Result<Self> {
+        let api_key =
+            match (api_key_manager.fetch_api_key(provider), cli_handler) {
+                (Err(Error::NoApiKey { .. }), Some(cli_handler)) => cli_handler
+                    .get_api_key()
+                    .map_err(|source| Error::KeyPrompt {
+                        provider: provider.to_string(),
+                        source: Box::new(source),
+                    })?,
+                (api_key_result, _) => api_key_result?,
+            };
+        Ok(Self {
+            api_key: Some(api_key),
+            ..Self::without_key(config)
+        })
+    }
+
+    /// Construct a provider for local backends that do not authenticate, skipping the
+    /// keyring lookup entirely.
+    pub fn without_key(config: &OnlineProviderOpts) -> Self {
         Self {
             url: config.url.clone(),
             model: config.model.clone(),
-            api_key,
+            context_window: config
+                .context_window
+                .or_else(|| tokens::default_context_window(&config.model)),
+            api_key: None,
+        }
+    }
+}
+
+/// Conversation state shared by every provider implementation. This is what a session
+/// persists, so it is independent of the provider that produced it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct ChatMemory {
+    #[serde(default)]
+    pub(crate) history: ChatHistory,
+    #[serde(default)]
+    pub(crate) system_prompt: Option<String>,
+    #[serde(default)]
+    pub(crate) tools: LLMTools,
+    /// Tokens the provider reported for the latest exchange.
+    #[serde(skip)]
+    pub(crate) usage: TokenUsage,
+    /// Local tools that can be enabled in `tools`.
+    #[serde(skip)]
+    pub(crate) toolbox: Toolbox,
+}
+
+impl ChatMemory {
+    fn new(config: &Configuration) -> Self {
+        Self {
+            history: ChatHistory::default(),
+            system_prompt: None,
+            tools: LLMTools::new(config),
+            usage: TokenUsage::default(),
+            toolbox: Toolbox::new(config),
+        }
+    }
+
+    /// The tools the model is offered in this conversation.
+    fn enabled_tools(&self) -> impl Iterator<Item = &dyn Tool> {
+        self.toolbox
+            .iter()
+            .filter(|tool| self.tools.functions.contains(tool.name()))
+    }
+
+    /// Run a tool the model called, recording what it returned or why it failed.
+    async fn run_tool_call(&self, call: &mut ToolCallData) {
+        let result = match self.toolbox.get(&call.name) {
+            Some(tool) if self.tools.functions.contains(&call.name) => {
+                self.toolbox.run(tool, call.arguments.clone()).await
+            }
+            _ => Err(anyhow::anyhow!("No tool called {} is enabled.", call.name)),
+        };
+        trace!("Tool call {} returned {:?}", call.name, result);
+        match result {
+            Ok(output) => call.output = output,
+            Err(err) => {
+                call.output = format!("{err:#}");
+                call.failed = true;
+            }
         }
     }
 }
@@ -51,135 +149,850 @@ impl OnlineProvider {
 trait ProviderImpl: Clone {
     fn provider_str() -> &'static str;
 
-    fn merge_tools(&mut self, tools: LLMTools);
-    fn flags_mut(&mut self) -> &mut LLMTools;
-    fn flags(&self) -> &LLMTools;
+    fn memory(&self) -> &ChatMemory;
+    fn memory_mut(&mut self) -> &mut ChatMemory;
 
-    fn update_memory(&mut self, prompt: String, response: String) -> anyhow::Result<()>;
-    fn add_chat_to_context(&mut self, chat: ChatHistoryItem) -> anyhow::Result<Option<usize>>;
-    fn append_chat_in_context(&mut self, index: usize, text: &str) -> anyhow::Result<()>;
-    fn clear_memory(&mut self) -> anyhow::Result<()>;
-    fn get_history(&self) -> &Vec<ChatHistoryItem>;
+    fn merge_tools(&mut self, tools: LLMTools) {
+        self.memory_mut().tools.merge(&tools);
+    }
+
+    fn flags_mut(&mut self) -> &mut LLMTools {
+        &mut self.memory_mut().tools
+    }
+
+    fn flags(&self) -> &LLMTools {
+        &self.memory().tools
+    }
+
+    fn update_memory(
+        &mut self,
+        prompt: String,
+        turn: Vec<ChatHistoryItem>,
+        response: String,
+    ) -> anyhow::Result<()> {
+        let history = &mut self.memory_mut().history;
+        history.push(ChatData::user(prompt).into());
+        for item in turn {
+            history.push(item);
+        }
+        history.push(ChatData::model(response).into());
+        Ok(())
+    }
+
+    /// Used when providing model context.
+    fn add_chat_to_context(&mut self, chat: ChatHistoryItem) -> anyhow::Result<Option<usize>> {
+        let memory = self.memory_mut();
+        match chat {
+            ChatHistoryItem::Chat(ChatData {
+                role: ChatRole::System,
+                text,
+                ..
+            }) => {
+                memory.system_prompt = Some(text);
+                Ok(None)
+            }
+            _ => Ok(Some(memory.history.push(chat))),
+        }
+    }
+
+    fn append_chat_in_context(&mut self, index: usize, text: &str) -> anyhow::Result<()> {
+        self.memory_mut()
+            .history
+            .append_text(index, text)
+            .context(format!("Failed to get chat at index {}", index))
+    }
+
+    fn clear_memory(&mut self) -> anyhow::Result<()> {
+        self.memory_mut().history.clear();
+        Ok(())
+    }
+
+    fn get_history(&self) -> &Vec<ChatHistoryItem> {
+        &self.memory().history
+    }
+
+    fn record_usage(&mut self, usage: TokenUsage) {
+        self.memory_mut().usage.merge(usage);
+    }
 }
 
-trait OnlineProviderImpl: ProviderImpl {
-    type ProviderApiResponse: DeserializeOwned;
-    type ProviderApiStreamResponse: DeserializeOwned;
+trait OnlineProviderImpl: ProviderImpl + Send + Sync {
+    type ProviderApiResponse: DeserializeOwned + Send;
+    type ProviderApiStreamResponse: DeserializeOwned + Send;
 
     fn build_chat_url(&self) -> anyhow::Result<reqwest::Url>;
     fn build_chat_stream_url(&self) -> anyhow::Result<reqwest::Url>;
-    fn build_chat_body(&self, prompt: impl Into<String>) -> serde_json::Value;
+    /// Cheap authenticated endpoint (usually the model list) used to verify the api key.
+    fn build_models_url(&self) -> anyhow::Result<reqwest::Url>;
+    /// Request body for `prompt`, followed by the tool calls already made while answering it
+    /// and any text the model wrote before making them.
+    fn build_chat_body(
+        &self,
+        prompt: impl Into<String>,
+        turn: &[ChatHistoryItem],
+        stream: bool,
+    ) -> serde_json::Value;
     fn get_http_client(&self) -> &reqwest::Client;
     fn decode_llm_response(&self, response: Self::ProviderApiResponse) -> anyhow::Result<String>;
     fn decode_llm_stream_response(
         &self,
         response: Self::ProviderApiStreamResponse,
     ) -> anyhow::Result<String>;
+    fn context_window(&self) -> Option<u64>;
+
+    /// Token counts from a response, when the provider reports them.
+    fn decode_usage(&self, _response: &Self::ProviderApiResponse) -> Option<TokenUsage> {
+        None
+    }
+
+    /// Token counts carried by a stream payload, usually only the last one.
+    fn decode_stream_usage(
+        &self,
+        _response: &Self::ProviderApiStreamResponse,
+    ) -> Option<TokenUsage> {
+        None
+    }
+
+    /// Tools the model asked to call in a response, not yet run.
+    fn decode_tool_calls(&self, _response: &Self::ProviderApiResponse) -> Vec<ToolCallData> {
+        Vec::new()
+    }
+
+    /// Tools the model asked to call in a stream payload, not yet run.
+    fn decode_stream_tool_calls(
+        &self,
+        _response: &Self::ProviderApiStreamResponse,
+    ) -> Vec<ToolCallData> {
+        Vec::new()
+    }
+
+    /// Pieces of tool calls in a stream payload, for providers that stream the arguments of a
+    /// call across several payloads instead of sending it whole.
+    fn decode_stream_tool_call_deltas(
+        &self,
+        _response: &Self::ProviderApiStreamResponse,
+    ) -> Vec<ToolCallDelta> {
+        Vec::new()
+    }
+
+    /// Tokens the request for `prompt` would use. Estimated locally from the request body
+    /// unless the provider can count them.
+    fn count_tokens(
+        &self,
+        prompt: String,
+    ) -> impl Future<Output = anyhow::Result<TokenCount>> + Send {
+        let body = self.build_chat_body(prompt, &[], false);
+        async move {
+            Ok(TokenCount {
+                tokens: tokens::estimate_tokens(&body.to_string()),
+                estimated: true,
+            })
+        }
+    }
+
+    /// Attach provider specific authentication to an outgoing request.
+    fn authorise_request(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        request
+    }
+
+    /// Whether a stream payload marks the end of the stream rather than carrying a response.
+    fn is_stream_done(&self, _data: &str) -> bool {
+        false
+    }
+
+    /// Wire format used by the provider's streaming endpoint.
+    fn stream_format(&self) -> StreamFormat {
+        StreamFormat::ServerSentEvents
+    }
+
+    /// Authorise and send a request. Only the redacted url and headers are logged, and the
+    /// url is redacted from network errors.
+    fn send_request(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> impl Future<Output = Result<reqwest::Response>> + Send {
+        async move {
+            let request = self
+                .authorise_request(request)
+                .build()
+                .map_err(redact::redact_error)?;
+            trace!(
+                "Sending {} {} to {} with headers {:?}",
+                request.method(),
+                redact::redact_url(request.url()),
+                Self::provider_str(),
+                redact::redact_headers(request.headers())
+            );
+            let response = self
+                .get_http_client()
+                .execute(request)
+                .await
+                .map_err(redact::redact_error)?;
+            trace!(
+                "{} responded with {}",
+                Self::provider_str(),
+                response.status()
+            );
+            Ok(response)
+        }
+    }
+
+    /// Stream the response to `prompt`. When the model calls tools they are run, reported as
+    /// [`StreamChunk::ToolCall`], and the results sent back until it gives a final answer.
+    fn complete_chat_stream(
+        &self,
+        prompt: String,
+    ) -> impl Future<Output = anyhow::Result<impl Stream<Item = anyhow::Result<StreamChunk>> + Send>>
+    + Send {
+        async move {
+            let request = self.request_stream(&prompt, &[]).await?;
+            let state = ToolLoop {
+                prompt,
+                request: Some(request),
+                turn: Vec::new(),
+                text: String::new(),
+                requested: Vec::new(),
+                partial: Vec::new(),
+                pending: VecDeque::new(),
+                rounds: 0,
+            };
+            Ok(stream::unfold(state, move |mut state| async move {
+                loop {
+                    if let Some(chunk) = state.pending.pop_front() {
+                        return Some((chunk, state));
+                    }
+                    match state.request.as_mut()?.next().await {
+                        Some(Ok(StreamPart::Chunk(chunk))) => {
+                            if let StreamChunk::Text(text) = &chunk {
+                                state.text.push_str(text);
+                            }
+                            return Some((Ok(chunk), state));
+                        }
+                        Some(Ok(StreamPart::ToolCall(call))) => state.requested.push(call),
+                        Some(Ok(StreamPart::ToolCallDelta(delta))) => {
+                            delta.merge_into(&mut state.partial)
+                        }
+                        Some(Err(err)) => {
+                            state.request = None;
+                            return Some((Err(err), state));
+                        }
+                        None => {
+                            state.request = None;
+                            let partial = std::mem::take(&mut state.partial);
+                            state
+                                .requested
+                                .extend(partial.into_iter().map(ToolCallDelta::into_call));
+                            if state.requested.is_empty() {
+                                return None;
+                            }
+                            state.rounds += 1;
+                            if state.rounds > MAX_TOOL_ROUNDS {
+                                state.pending.push_back(Err(too_many_tool_rounds()));
+                                continue;
+                            }
+                            let text = std::mem::take(&mut state.text);
+                            if !text.is_empty() {
+                                state.turn.push(ChatData::model(text).into());
+                            }
+                            let requested = std::mem::take(&mut state.requested);
+                            for (index, mut call) in requested.into_iter().enumerate() {
+                                call.parallel = index > 0;
+                                self.memory().run_tool_call(&mut call).await;
+                                state.turn.push(call.clone().into());
+                                state.pending.push_back(Ok(StreamChunk::ToolCall(call)));
+                            }
+                            match self.request_stream(&state.prompt, &state.turn).await {
+                                Ok(request) => state.request = Some(request),
+                                Err(err) => state.pending.push_back(Err(err)),
+                            }
+                        }
+                    }
+                }
+            }))
+        }
+    }
+
+    /// Send one streaming request, decoding the payloads as they arrive.
+    fn request_stream(
+        &self,
+        prompt: &str,
+        turn: &[ChatHistoryItem],
+    ) -> impl Future<Output = anyhow::Result<BoxStream<'_, anyhow::Result<StreamPart>>>> + Send
+    {
+        let body = self.build_chat_body(prompt, turn, true);
+        async move {
+            let request = self
+                .get_http_client()
+                .post(self.build_chat_stream_url()?)
+                .json(&body);
+            let response = self
+                .send_request(request)
+                .await
+                .context("Request failed to LLM Provider.")?;
+            let bytes = ensure_success(response).await?.bytes_stream();
+            let payloads = match self.stream_format() {
+                StreamFormat::ServerSentEvents => bytes
+                    .eventsource()
+                    .map(|event| match event {
+                        Ok(event) => Ok(event.data),
+                        // Kept as a network error when the connection drops mid response.
+                        Err(EventStreamError::Transport(err)) => {
+                            Err(anyhow::Error::from(Error::Network(err))
+                                .context("Failed to create bytes stream."))
+                        }
+                        Err(err) => {
+                            Err(anyhow::Error::from(err).context("Failed to create bytes stream."))
+                        }
+                    })
+                    .left_stream(),
+                StreamFormat::NewlineDelimitedJson => json_lines(bytes).right_stream(),
+            };
+            let stream = payloads
+                .take_while(|data| {
+                    future::ready(!matches!(data, Ok(data) if self.is_stream_done(data)))
+                })
+                .flat_map(|data| {
+                    let parts = match data.and_then(|data| {
+                        serde_json::from_str::<Self::ProviderApiStreamResponse>(&data)
+                            .context("Failed to decode llm response")
+                    }) {
+                        Ok(value) => {
+                            let usage = self
+                                .decode_stream_usage(&value)
+                                .map(|usage| Ok(StreamPart::Chunk(StreamChunk::Usage(usage))));
+                            let calls = self
+                                .decode_stream_tool_calls(&value)
+                                .into_iter()
+                                .map(|call| Ok(StreamPart::ToolCall(call)))
+                                .chain(
+                                    self.decode_stream_tool_call_deltas(&value)
+                                        .into_iter()
+                                        .map(|delta| Ok(StreamPart::ToolCallDelta(delta))),
+                                );
+                            // Payloads with only a call or usage carry no text.
+                            let text = self
+                                .decode_llm_stream_response(value)
+                                .map(|text| StreamPart::Chunk(StreamChunk::Text(text)));
+                            std::iter::once(text)
+                                .filter(|text| {
+                                    !matches!(text, Ok(StreamPart::Chunk(StreamChunk::Text(text))) if text.is_empty())
+                                })
+                                .chain(usage)
+                                .chain(calls)
+                                .collect()
+                        }
+                        Err(err) => vec![Err(err)],
+                    };
+                    stream::iter(parts)
+                });
+
+            Ok(stream.boxed())
+        }
+    }
+
+    /// Shorten the conversation when a request for `prompt` would not fit the context window,
+    /// returning what changed so other copies of the history can follow.
+    fn compact(
+        &mut self,
+        prompt: &str,
+        options: &CompactionOptions,
+    ) -> impl Future<Output = anyhow::Result<Option<Compaction>>> + Send {
+        async move {
+            let Some(window) = self.context_window() else {
+                return Ok(None);
+            };
+            let budget = compaction::budget(window, options.threshold);
+            let removed = compaction::plan(self.memory(), prompt, options.strategy, budget);
+            if removed.is_empty() {
+                return Ok(None);
+            }
+            let compaction = match options.strategy {
+                CompactionStrategy::Summarise => {
+                    let mut summariser = self.clone();
+                    summariser.memory_mut().history.clear();
+                    summariser.memory_mut().system_prompt = None;
+                    // The summary is written from the transcript alone, without calling tools.
+                    summariser.memory_mut().tools = LLMTools::default();
+                    let summary = OnlineProviderImpl::complete_chat(
+                        &mut summariser,
+                        compaction::summary_prompt(&self.memory().history, &removed),
+                    )
+                    .await
+                    .context("Failed to summarise earlier messages.")?;
+                    Compaction::summarised(&self.memory().history, removed, &summary)
+                }
+                _ => Compaction::dropped(&self.memory().history, removed),
+            };
+            trace!("Compacting history: {:?}", compaction);
+            compaction.apply(&mut self.memory_mut().history);
+            Ok(Some(compaction))
+        }
+    }
+
+    fn verify_api_key(&self) -> impl Future<Output = anyhow::Result<()>> + Send {
+        async move {
+            let request = self.get_http_client().get(self.build_models_url()?);
+            let response = self
+                .send_request(request)
+                .await
+                .context("Request failed to LLM Provider.")?;
+            ensure_success(response).await?;
+            Ok(())
+        }
+    }
+
+    /// Get the response to `prompt`, running any tools the model calls and sending the results
+    /// back until it gives a final answer.
+    fn complete_chat(
+        &mut self,
+        prompt: String,
+    ) -> impl Future<Output = anyhow::Result<String>> + Send {
+        async move {
+            let mut turn = Vec::new();
+            let mut rounds = 0;
+            let decoded = loop {
+                let request = self
+                    .get_http_client()
+                    .post(self.build_chat_url()?)
+                    .json(&self.build_chat_body(prompt.clone(), &turn, false));
+                let response = self
+                    .send_request(request)
+                    .await
+                    .context("Request failed to LLM Provider.")?;
+                let response = ensure_success(response)
+                    .await?
+                    .json::<Self::ProviderApiResponse>()
+                    .await
+                    .context("Failed to decode LLM response into JSON")?;
+                self.memory_mut().usage = self.decode_usage(&response).unwrap_or_default();
+                let requested = self.decode_tool_calls(&response);
+                if requested.is_empty() {
+                    break self.decode_llm_response(response)?;
+                }
+                rounds += 1;
+                if rounds > MAX_TOOL_ROUNDS {
+                    return Err(too_many_tool_rounds());
+                }
+                // Text alongside the calls, which is not the answer.
+                let text = self.decode_llm_response(response).unwrap_or_default();
+                if !text.is_empty() {
+                    turn.push(ChatData::model(text).into());
+                }
+                for (index, mut call) in requested.into_iter().enumerate() {
+                    call.parallel = index > 0;
+                    self.memory().run_tool_call(&mut call).await;
+                    turn.push(call.into());
+                }
+            };
+            self.update_memory(prompt, turn, decoded.clone())
+                .context("Failed to update memory.")?;
+            Ok(decoded)
+        }
+    }
+}
+
+/// Stop a model that keeps calling tools instead of answering after this many rounds.
+const MAX_TOOL_ROUNDS: usize = 10;
+
+fn too_many_tool_rounds() -> anyhow::Error {
+    anyhow::anyhow!("The model called tools {MAX_TOOL_ROUNDS} times without giving an answer.")
+}
+
+/// A piece of one streamed request, before any tools it calls are run.
+enum StreamPart {
+    Chunk(StreamChunk),
+    ToolCall(ToolCallData),
+    ToolCallDelta(ToolCallDelta),
+}
+
+/// A piece of a tool call whose arguments are streamed across several payloads.
+#[derive(Debug, Default)]
+struct ToolCallDelta {
+    /// Position of the call in the response, the same for all of its pieces.
+    index: usize,
+    id: Option<String>,
+    name: Option<String>,
+    /// The next part of the JSON encoded arguments.
+    arguments: String,
+}
+
+impl ToolCallDelta {
+    /// Add this piece to the call it belongs to in `partial`, or start a new one.
+    fn merge_into(self, partial: &mut Vec<ToolCallDelta>) {
+        match partial.iter_mut().find(|call| call.index == self.index) {
+            Some(call) => {
+                call.id = call.id.take().or(self.id);
+                call.name = call.name.take().or(self.name);
+                call.arguments.push_str(&self.arguments);
+            }
+            None => partial.push(self),
+        }
+    }
+
+    /// The whole call, once every piece has arrived.
+    fn into_call(self) -> ToolCallData {
+        ToolCallData {
+            id: self.id,
+            ..ToolCallData::requested(
+                self.name.unwrap_or_default(),
+                decode_arguments(&self.arguments),
+                None,
+            )
+        }
+    }
+}
+
+/// Arguments of a call sent as a JSON encoded string. Ones that are not valid JSON are passed
+/// on as the string, for the tool to reject.
+fn decode_arguments(arguments: &str) -> serde_json::Value {
+    match arguments.trim() {
+        "" => serde_json::json!({}),
+        trimmed => serde_json::from_str(trimmed)
+            .unwrap_or_else(|_| serde_json::Value::String(arguments.to_string())),
+    }
+}
+
+/// State of a streamed response across the requests made while the model calls tools.
+struct ToolLoop<'a> {
+    prompt: String,
+    request: Option<BoxStream<'a, anyhow::Result<StreamPart>>>,
+    /// Calls run so far and the text written alongside them, sent with every later request.
+    turn: Vec<ChatHistoryItem>,
+    /// Text of the current request.
+    text: String,
+    /// Calls the current request asked for, run once it finishes.
+    requested: Vec<ToolCallData>,
+    /// Calls of the current request whose pieces are still arriving.
+    partial: Vec<ToolCallDelta>,
+    pending: VecDeque<anyhow::Result<StreamChunk>>,
+    rounds: usize,
+}
+
+/// A piece of a streamed response.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum StreamChunk {
+    Text(String),
+    Usage(TokenUsage),
+    /// A tool the model called, after it ran.
+    ToolCall(ToolCallData),
+}
+
+/// Turn a non 2xx response into an [`Error::Api`] carrying the provider's error body.
+async fn ensure_success(response: reqwest::Response) -> Result<reqwest::Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let body = response.text().await.unwrap_or_default();
+    Err(Error::Api { status, body })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum StreamFormat {
+    ServerSentEvents,
+    NewlineDelimitedJson,
+}
+
+/// Split a streamed response body into its non-empty lines, for providers that stream one
+/// JSON document per line instead of using server sent events.
+fn json_lines<B: AsRef<[u8]>>(
+    bytes: impl Stream<Item = reqwest::Result<B>>,
+) -> impl Stream<Item = anyhow::Result<String>> {
+    stream::unfold(
+        (Box::pin(bytes), Vec::<u8>::new()),
+        |(mut bytes, mut buffer)| async move {
+            loop {
+                if let Some(end) = buffer.iter().position(|byte| *byte == b'\n') {
+                    let line = buffer.drain(..=end).collect::<Vec<_>>();
+                    let line = String::from_utf8_lossy(&line).trim().to_string();
+                    if !line.is_empty() {
+                        return Some((Ok(line), (bytes, buffer)));
+                    }
+                    continue;
+                }
+                match bytes.next().await {
+                    Some(Ok(chunk)) => buffer.extend_from_slice(chunk.as_ref()),
+                    Some(Err(err)) => {
+                        let err = anyhow::Error::from(Error::Network(err))
+                            .context("Failed to create bytes stream.");
+                        return Some((Err(err), (bytes, buffer)));
+                    }
+                    None => {
+                        let line = String::from_utf8_lossy(&buffer).trim().to_string();
+                        buffer.clear();
+                        return (!line.is_empty()).then_some((Ok(line), (bytes, buffer)));
+                    }
+                }
+            }
+        },
+    )
+}
+
+const GEMINI_PROVIDER: &str = "gemini";
+const OPENAI_PROVIDER: &str = "openai";
+const CLAUDE_PROVIDER: &str = "claude";
+const OLLAMA_PROVIDER: &str = "ollama";
+
+/// Object safe view of a provider backend, so providers can be stored and dispatched as
+/// `Box<dyn LlmProvider>`. Implemented for every [`OnlineProviderImpl`].
+trait LlmProvider: Send + Sync + std::fmt::Debug {
+    fn complete_chat(&mut self, prompt: String) -> BoxFuture<'_, anyhow::Result<String>>;
+    fn complete_chat_stream(
+        &self,
+        prompt: String,
+    ) -> BoxFuture<'_, anyhow::Result<BoxStream<'_, anyhow::Result<StreamChunk>>>>;
+
+    fn verify_api_key(&self) -> BoxFuture<'_, anyhow::Result<()>>;
+    fn compact<'a>(
+        &'a mut self,
+        prompt: &'a str,
+        options: &'a CompactionOptions,
+    ) -> BoxFuture<'a, anyhow::Result<Option<Compaction>>>;
+    fn count_tokens(&self, prompt: String) -> BoxFuture<'_, anyhow::Result<TokenCount>>;
+    fn context_window(&self) -> Option<u64>;
+    fn record_usage(&mut self, usage: TokenUsage);
+    fn reset_usage(&mut self);
+
+    fn merge_tools(&mut self, tools: LLMTools);
+    fn flags_mut(&mut self) -> &mut LLMTools;
+    fn flags(&self) -> &LLMTools;
+
+    fn add_chat_to_context(&mut self, chat: ChatHistoryItem) -> anyhow::Result<Option<usize>>;
+    fn append_chat_in_context(&mut self, index: usize, text: &str) -> anyhow::Result<()>;
+    fn clear_memory(&mut self) -> anyhow::Result<()>;
+    fn get_history(&self) -> &Vec<ChatHistoryItem>;
+    fn update_memory(
+        &mut self,
+        prompt: String,
+        turn: Vec<ChatHistoryItem>,
+        response: String,
+    ) -> anyhow::Result<()>;
+    fn chat_memory(&self) -> &ChatMemory;
+    fn history_mut(&mut self) -> &mut ChatHistory;
+    fn toolbox_mut(&mut self) -> &mut Toolbox;
+    fn restore_memory(&mut self, memory: ChatMemory);
 
-    async fn complete_chat_stream(
+    fn clone_box(&self) -> Box<dyn LlmProvider>;
+}
+
+impl<T> LlmProvider for T
+where
+    T: OnlineProviderImpl + std::fmt::Debug + 'static,
+{
+    fn complete_chat(&mut self, prompt: String) -> BoxFuture<'_, anyhow::Result<String>> {
+        OnlineProviderImpl::complete_chat(self, prompt).boxed()
+    }
+
+    fn complete_chat_stream(
         &self,
         prompt: String,
-    ) -> anyhow::Result<impl Stream<Item = anyhow::Result<String>>> {
-        let stream = self
-            .get_http_client()
-            .post(self.build_chat_stream_url()?)
-            .json(&self.build_chat_body(prompt))
-            .send()
-            .await
-            .context("Request failed to LLM Provider.")?
-            .bytes_stream()
-            .eventsource()
-            .map(|bytes| {
-                let event = bytes.context("Failed to create bytes stream.")?;
-                let data = event.data;
-                let value = serde_json::from_str::<Self::ProviderApiStreamResponse>(&data)
-                    .context("Failed to decode llm response")?;
-                self.decode_llm_stream_response(value)
-            });
-
-        Ok(stream)
-    }
-
-    async fn complete_chat(&mut self, prompt: String) -> anyhow::Result<String> {
-        let response = self
-            .get_http_client()
-            .post(self.build_chat_url()?)
-            .json(&self.build_chat_body(prompt.clone()))
-            .send()
-            .await
-            .context("Request failed to LLM Provider.")?
-            .json::<Self::ProviderApiResponse>()
-            .await
-            .context("Failed to decode LLM response into JSON")?;
-        let decoded = self.decode_llm_response(response)?;
-        self.update_memory(prompt, decoded.clone())
-            .context("Failed to update memory.")?;
-        Ok(decoded)
-    }
-}
-
-#[derive(derive_more::From, Debug, Clone)]
-pub enum Provider {
-    Gemini(GeminiProvider),
-}
-
-const GEMINI_PROVIDER: &'static str = "gemini";
+    ) -> BoxFuture<'_, anyhow::Result<BoxStream<'_, anyhow::Result<StreamChunk>>>> {
+        OnlineProviderImpl::complete_chat_stream(self, prompt)
+            .map_ok(StreamExt::boxed)
+            .boxed()
+    }
+
+    fn verify_api_key(&self) -> BoxFuture<'_, anyhow::Result<()>> {
+        OnlineProviderImpl::verify_api_key(self).boxed()
+    }
+
+    fn compact<'a>(
+        &'a mut self,
+        prompt: &'a str,
+        options: &'a CompactionOptions,
+    ) -> BoxFuture<'a, anyhow::Result<Option<Compaction>>> {
+        OnlineProviderImpl::compact(self, prompt, options).boxed()
+    }
+
+    fn count_tokens(&self, prompt: String) -> BoxFuture<'_, anyhow::Result<TokenCount>> {
+        OnlineProviderImpl::count_tokens(self, prompt).boxed()
+    }
+
+    fn context_window(&self) -> Option<u64> {
+        OnlineProviderImpl::context_window(self)
+    }
+
+    fn record_usage(&mut self, usage: TokenUsage) {
+        ProviderImpl::record_usage(self, usage)
+    }
+
+    fn reset_usage(&mut self) {
+        ProviderImpl::memory_mut(self).usage = TokenUsage::default();
+    }
+
+    fn merge_tools(&mut self, tools: LLMTools) {
+        ProviderImpl::merge_tools(self, tools)
+    }
+
+    fn flags_mut(&mut self) -> &mut LLMTools {
+        ProviderImpl::flags_mut(self)
+    }
+
+    fn flags(&self) -> &LLMTools {
+        ProviderImpl::flags(self)
+    }
+
+    fn add_chat_to_context(&mut self, chat: ChatHistoryItem) -> anyhow::Result<Option<usize>> {
+        ProviderImpl::add_chat_to_context(self, chat)
+    }
+
+    fn append_chat_in_context(&mut self, index: usize, text: &str) -> anyhow::Result<()> {
+        ProviderImpl::append_chat_in_context(self, index, text)
+    }
+
+    fn clear_memory(&mut self) -> anyhow::Result<()> {
+        ProviderImpl::clear_memory(self)
+    }
+
+    fn get_history(&self) -> &Vec<ChatHistoryItem> {
+        ProviderImpl::get_history(self)
+    }
+
+    fn update_memory(
+        &mut self,
+        prompt: String,
+        turn: Vec<ChatHistoryItem>,
+        response: String,
+    ) -> anyhow::Result<()> {
+        ProviderImpl::update_memory(self, prompt, turn, response)
+    }
+
+    fn chat_memory(&self) -> &ChatMemory {
+        ProviderImpl::memory(self)
+    }
+
+    fn history_mut(&mut self) -> &mut ChatHistory {
+        &mut ProviderImpl::memory_mut(self).history
+    }
+
+    fn toolbox_mut(&mut self) -> &mut Toolbox {
+        &mut ProviderImpl::memory_mut(self).toolbox
+    }
+
+    fn restore_memory(&mut self, memory: ChatMemory) {
+        let current = ProviderImpl::memory_mut(self);
+        *current = ChatMemory {
+            toolbox: std::mem::take(&mut current.toolbox),
+            ..memory
+        };
+    }
+
+    fn clone_box(&self) -> Box<dyn LlmProvider> {
+        Box::new(self.clone())
+    }
+}
+
+#[derive(Debug)]
+pub struct Provider {
+    inner: Box<dyn LlmProvider>,
+    compaction: CompactionOptions,
+}
+
+impl Clone for Provider {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone_box(),
+            compaction: self.compaction.clone(),
+        }
+    }
+}
 
 impl Provider {
     pub async fn complete_chat(&mut self, prompt: String) -> anyhow::Result<String> {
-        match self {
-            Self::Gemini(prov) => prov.complete_chat(prompt).await,
-        }
+        self.compact(&prompt).await?;
+        self.inner.complete_chat(prompt).await
     }
 
-    pub async fn complete_chat_stream(
+    pub(crate) async fn complete_chat_stream(
         &mut self,
         prompt: String,
-    ) -> anyhow::Result<impl Stream<Item = anyhow::Result<String>>> {
-        match self {
-            Self::Gemini(prov) => prov.complete_chat_stream(prompt).await,
+    ) -> anyhow::Result<BoxStream<'_, anyhow::Result<StreamChunk>>> {
+        self.compact(&prompt).await?;
+        self.reset_usage();
+        self.inner.complete_chat_stream(prompt).await
+    }
+
+    /// Compact the conversation, as configured, if a request for `prompt` would not fit the
+    /// context window. Requests do this themselves; call it first to report what changed.
+    pub(crate) async fn compact(&mut self, prompt: &str) -> anyhow::Result<Option<Compaction>> {
+        self.inner.compact(prompt, &self.compaction).await
+    }
+
+    /// Tokens a request for `prompt` would use with the current conversation.
+    pub async fn count_tokens(&self, prompt: String) -> anyhow::Result<TokenCount> {
+        self.inner.count_tokens(prompt).await
+    }
+
+    pub fn context_window(&self) -> Option<u64> {
+        self.inner.context_window()
+    }
+
+    /// Add token counts reported while streaming a response.
+    pub(crate) fn record_usage(&mut self, usage: TokenUsage) {
+        self.inner.record_usage(usage)
+    }
+
+    /// Forget the counts for the previous exchange when starting a new one.
+    pub(crate) fn reset_usage(&mut self) {
+        self.inner.reset_usage()
+    }
+
+    /// Token counts for the latest exchange, estimating any the provider did not report.
+    pub fn token_report(&self) -> TokenReport {
+        let memory = self.memory();
+        let history = &memory.history;
+        let (context, reply) = match history.last() {
+            Some(ChatHistoryItem::Chat(chat)) if chat.role == ChatRole::Model => (
+                &history[..history.len() - 1],
+                tokens::estimate_tokens(&chat.text),
+            ),
+            _ => (&history[..], 0),
+        };
+        let context = memory
+            .system_prompt
+            .iter()
+            .map(|prompt| tokens::estimate_tokens(prompt))
+            .chain(context.iter().map(tokens::estimate_item_tokens))
+            .sum();
+        TokenReport {
+            prompt: memory.usage.prompt.unwrap_or(context),
+            response: memory.usage.response.unwrap_or(reply),
+            estimated: memory.usage.prompt.is_none() || memory.usage.response.is_none(),
+            context_window: self.context_window(),
         }
     }
 
+    /// Make a cheap authenticated request to check the provider accepts the api key.
+    pub async fn verify_api_key(&self) -> anyhow::Result<()> {
+        self.inner.verify_api_key().await
+    }
+
+    /// Build the provider selected by `config.provider` from the [`ProviderRegistry`].
     pub fn new(
         config: &Configuration,
         api_key_manager: &APIKeyManager,
         cli_handler: Option<&CliHandler>,
-    ) -> Provider {
-        match config.provider.as_str() {
-            GEMINI_PROVIDER => GeminiProvider::new(&config, api_key_manager, cli_handler).into(),
-            _ => panic!(
-                "invalid provider string reference. Recieved: {:?}",
-                config.provider.as_str()
-            ),
-        }
+    ) -> Result<Provider> {
+        ProviderRegistry::new(config)?.build(&config.provider, config, api_key_manager, cli_handler)
     }
 
     pub fn merge_tools(&mut self, tools: LLMTools) {
-        match self {
-            Self::Gemini(provider) => provider.merge_tools(tools),
-        }
+        self.inner.merge_tools(tools)
     }
 
     pub fn flags_mut(&mut self) -> &mut LLMTools {
-        match self {
-            Self::Gemini(provider) => provider.flags_mut(),
-        }
+        self.inner.flags_mut()
     }
 
     pub fn flags(&self) -> &LLMTools {
-        match self {
-            Self::Gemini(provider) => provider.flags(),
-        }
+        self.inner.flags()
     }
 
     pub(crate) fn add_chat_to_context(
         &mut self,
         chat: ChatHistoryItem,
     ) -> anyhow::Result<Option<usize>> {
-        match self {
-            Self::Gemini(provider) => provider.add_chat_to_context(chat),
-        }
+        self.inner.add_chat_to_context(chat)
     }
 
     pub(crate) fn append_chat_in_context(
@@ -187,37 +1000,82 @@ impl Provider {
         index: usize,
         text: &str,
     ) -> anyhow::Result<()> {
-        match self {
-            Self::Gemini(provider) => provider.append_chat_in_context(index, text),
-        }
+        self.inner.append_chat_in_context(index, text)
     }
 
     pub fn clear_history(&mut self) -> anyhow::Result<()> {
-        match self {
-            Self::Gemini(provider) => provider.clear_memory(),
-        }
+        self.inner.clear_memory()
     }
 
     pub(crate) fn get_history(&self) -> &Vec<ChatHistoryItem> {
-        match self {
-            Self::Gemini(provider) => provider.get_history(),
-        }
+        self.inner.get_history()
+    }
+
+    /// Record a prompt, the tools called while answering it and the response, for responses
+    /// that were streamed.
+    pub(crate) fn update_memory(
+        &mut self,
+        prompt: String,
+        turn: Vec<ChatHistoryItem>,
+        response: String,
+    ) -> anyhow::Result<()> {
+        self.inner.update_memory(prompt, turn, response)
+    }
+
+    pub(crate) fn memory(&self) -> &ChatMemory {
+        self.inner.chat_memory()
+    }
+
+    /// The conversation tree, for editing messages and switching between branches.
+    pub(crate) fn history_mut(&mut self) -> &mut ChatHistory {
+        self.inner.history_mut()
+    }
+
+    /// Offer `tools` to the model from now on, e.g. those of MCP servers. Returns the names of
+    /// those left out because another tool has the name.
+    pub(crate) fn add_tools(&mut self, tools: Vec<Arc<dyn Tool>>) -> Vec<String> {
+        let names = tools
+            .iter()
+            .map(|tool| tool.name().to_string())
+            .collect::<Vec<_>>();
+        let skipped = self.inner.toolbox_mut().extend(tools);
+        self.flags_mut()
+            .functions
+            .extend(names.into_iter().filter(|name| !skipped.contains(name)));
+        skipped
+    }
+
+    /// Ask `approver` before running tool calls with side effects.
+    pub(crate) fn set_approver(&mut self, approver: Arc<dyn Approver>) {
+        self.inner.toolbox_mut().set_approver(approver)
+    }
+
+    /// Replace the conversation, e.g. with one loaded from a saved session.
+    pub(crate) fn restore_memory(&mut self, memory: ChatMemory) {
+        self.inner.restore_memory(memory)
     }
 }
 
-#[derive(Default, Debug, Clone)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct LLMTools {
     pub search: bool,
+    /// Names of the local tools the model may call.
+    pub functions: BTreeSet<String>,
 }
 
 impl LLMTools {
     pub fn new(config: &Configuration) -> Self {
+        let tool_options = config.tools.as_ref();
         LLMTools {
-            search: config
-                .tools
-                .as_ref()
+            search: tool_options
                 .and_then(|tool_options| tool_options.search_default)
                 .unwrap_or_default(),
+            functions: tool_options
+                .and_then(|tool_options| tool_options.functions_default.clone())
+                .unwrap_or_default()
+                .into_iter()
+                .collect(),
         }
     }
 
@@ -225,10 +1083,7 @@ impl LLMTools {
         if tool_flags.search {
             self.search = true
         }
-    }
-
-    pub fn toggle_search(&mut self) {
-        self.search = !self.search
+        self.functions.extend(tool_flags.functions.iter().cloned());
     }
 }
 
@@ -238,24 +1093,143 @@ pub(crate) enum ChatRole {
     User,
     Model,
     System,
+    /// Stands in for earlier messages compacted to fit the context window, sent as a user message.
+    Context,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub(crate) struct ChatData {
     pub(crate) role: ChatRole,
     pub(crate) text: String,
+    /// The response was stopped before the model finished it.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub(crate) truncated: bool,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub(crate) struct FileUploadData {
     pub(crate) text: String,
     pub(crate) relative_filepath: String,
 }
 
-#[derive(Debug, From, Clone)]
+/// A local tool the model called while answering, and what it returned.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct ToolCallData {
+    pub(crate) name: String,
+    /// Identifier the provider gave the call, which the result must refer to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) id: Option<String>,
+    pub(crate) arguments: serde_json::Value,
+    /// Opaque token some providers attach to a call and expect back with it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) signature: Option<String>,
+    /// What the tool returned, or why it could not run, as sent back to the model.
+    #[serde(default)]
+    pub(crate) output: String,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub(crate) failed: bool,
+    /// Made in the same response as the call before it.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub(crate) parallel: bool,
+}
+
+impl ToolCallData {
+    /// A call the model asked for, before it has run.
+    pub(crate) fn requested(
+        name: String,
+        arguments: serde_json::Value,
+        signature: Option<String>,
+    ) -> Self {
+        Self {
+            name,
+            id: None,
+            arguments,
+            signature,
+            output: String::new(),
+            failed: false,
+            parallel: false,
+        }
+    }
+
+    /// The id the provider gave the call, or one made up from its position in the request for
+    /// calls without one, e.g. those replayed from a session with another provider.
+    fn id_or_position(&self, entry: usize, index: usize) -> String {
+        self.id
+            .clone()
+            .unwrap_or_else(|| format!("call_{entry}_{index}"))
+    }
+}
+
+/// History as providers send it, where the calls made in one response form a single model
+/// entry, together with any text the model wrote before them.
+enum HistoryEntry<'a> {
+    /// Text from the user or the model.
+    Message { role: ChatRole, text: Cow<'a, str> },
+    ToolCalls {
+        text: Option<&'a str>,
+        calls: Vec<&'a ToolCallData>,
+    },
+}
+
+/// The entries `history` is sent as. File uploads and compacted context are user messages, and
+/// system messages are left out as every provider takes the system prompt on its own.
+fn history_entries<'a>(
+    history: impl IntoIterator<Item = &'a ChatHistoryItem>,
+) -> Vec<HistoryEntry<'a>> {
+    let mut entries = Vec::new();
+    let mut previous: Option<&ChatHistoryItem> = None;
+    for item in history {
+        match (item, previous) {
+            (ChatHistoryItem::ToolCall(call), Some(ChatHistoryItem::ToolCall(_)))
+                if call.parallel =>
+            {
+                if let Some(HistoryEntry::ToolCalls { calls, .. }) = entries.last_mut() {
+                    calls.push(call);
+                }
+            }
+            (ChatHistoryItem::ToolCall(call), Some(ChatHistoryItem::Chat(chat)))
+                if chat.role == ChatRole::Model =>
+            {
+                entries.pop();
+                entries.push(HistoryEntry::ToolCalls {
+                    text: Some(&chat.text),
+                    calls: vec![call],
+                });
+            }
+            (ChatHistoryItem::ToolCall(call), _) => entries.push(HistoryEntry::ToolCalls {
+                text: None,
+                calls: vec![call],
+            }),
+            (ChatHistoryItem::Chat(chat), _) => {
+                let role = match chat.role {
+                    ChatRole::System => continue,
+                    ChatRole::Model => ChatRole::Model,
+                    ChatRole::User | ChatRole::Context => ChatRole::User,
+                };
+                entries.push(HistoryEntry::Message {
+                    role,
+                    text: Cow::Borrowed(&chat.text),
+                });
+            }
+            (ChatHistoryItem::FileUpload(file), _) => entries.push(HistoryEntry::Message {
+                role: ChatRole::User,
+                text: Cow::Owned(format!(
+                    "## <{}> Contents below ##\n{}",
+                    file.relative_filepath, file.text
+                )),
+            }),
+        }
+        previous = Some(item);
+    }
+    entries
+}
+
+#[derive(Debug, From, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
 pub(crate) enum ChatHistoryItem {
     FileUpload(FileUploadData),
     Chat(ChatData),
+    ToolCall(ToolCallData),
 }
 
 impl ChatData {
@@ -263,6 +1237,7 @@ impl ChatData {
         Self {
             role: ChatRole::User,
             text,
+            truncated: false,
         }
     }
 
@@ -270,6 +1245,24 @@ impl ChatData {
         Self {
             role: ChatRole::Model,
             text,
+            truncated: false,
+        }
+    }
+
+    pub fn system(text: String) -> Self {
+        Self {
+            role: ChatRole::System,
+            text,
+            truncated: false,
+        }
+    }
+
+    /// A message standing in for earlier ones that were compacted.
+    pub fn context(text: String) -> Self {
+        Self {
+            role: ChatRole::Context,
+            text,
+            truncated: false,
         }
     }
 }
@@ -280,6 +1273,7 @@ impl ChatRole {
             Self::User => "User",
             Self::Model => "LLM",
             Self::System => "System Instructions",
+            Self::Context => "Earlier context",
         }
     }
 }
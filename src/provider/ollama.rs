@@ -0,0 +1,254 @@
+use ollama_api_response::OllamaApiResponse;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::configuration::Configuration;
+
+use anyhow::{Context, anyhow};
+
+use super::{
+    ChatData, ChatHistoryItem, ChatMemory, ChatRole, HistoryEntry, OLLAMA_PROVIDER, OnlineProvider,
+    OnlineProviderImpl, ProviderImpl, Result, StreamFormat, TokenUsage, ToolCallData,
+    history_entries,
+};
+
+/// Provider for a local Ollama server. Ollama does not authenticate requests, so no api key
+/// is fetched from the keyring.
+#[derive(Debug, Clone)]
+pub struct OllamaProvider {
+    provider: OnlineProvider,
+    http_client: reqwest::Client,
+    memory: ChatMemory,
+}
+
+impl ProviderImpl for OllamaProvider {
+    fn provider_str() -> &'static str {
+        OLLAMA_PROVIDER
+    }
+
+    fn memory(&self) -> &ChatMemory {
+        &self.memory
+    }
+
+    fn memory_mut(&mut self) -> &mut ChatMemory {
+        &mut self.memory
+    }
+}
+
+impl OnlineProviderImpl for OllamaProvider {
+    type ProviderApiResponse = OllamaApiResponse;
+    type ProviderApiStreamResponse = OllamaApiResponse;
+
+    fn build_chat_url(&self) -> anyhow::Result<Url> {
+        reqwest::Url::parse(&self.provider.url)
+            .context("Failed to parse provider url")?
+            .join("api/chat")
+            .context("Failed to build ollama url.")
+    }
+
+    fn build_chat_stream_url(&self) -> anyhow::Result<Url> {
+        self.build_chat_url()
+    }
+
+    fn build_models_url(&self) -> anyhow::Result<Url> {
+        reqwest::Url::parse(&self.provider.url)
+            .context("Failed to parse provider url")?
+            .join("api/tags")
+            .context("Failed to build ollama models url.")
+    }
+
+    fn build_chat_body(
+        &self,
+        prompt: impl Into<String>,
+        turn: &[ChatHistoryItem],
+        stream: bool,
+    ) -> serde_json::Value {
+        let mut messages = Vec::new();
+        if let Some(instructions) = &self.memory.system_prompt {
+            messages.push(json!({
+                "role": "system",
+                "content": instructions
+            }));
+        }
+        let new_chat = ChatData::user(prompt.into()).into();
+        let history = self.memory.history.iter().chain([&new_chat]).chain(turn);
+        for entry in history_entries(history) {
+            Self::serialise_entry(&mut messages, entry);
+        }
+
+        let mut body = json!({
+            "model": self.provider.model,
+            "messages": messages,
+            "stream": stream
+        });
+        let tools = self.build_tools();
+        if !tools.is_empty() {
+            body["tools"] = json!(tools);
+        }
+        body
+    }
+
+    fn get_http_client(&self) -> &reqwest::Client {
+        &self.http_client
+    }
+
+    fn stream_format(&self) -> StreamFormat {
+        StreamFormat::NewlineDelimitedJson
+    }
+
+    fn decode_llm_response(&self, response: OllamaApiResponse) -> anyhow::Result<String> {
+        if let Some(error) = response.error {
+            return Err(anyhow!("Ollama error: {}", error));
+        }
+        Ok(response
+            .message
+            .context("Ollama response has no message")?
+            .content)
+    }
+
+    fn decode_llm_stream_response(
+        &self,
+        response: Self::ProviderApiStreamResponse,
+    ) -> anyhow::Result<String> {
+        self.decode_llm_response(response)
+    }
+
+    fn context_window(&self) -> Option<u64> {
+        self.provider.context_window
+    }
+
+    fn decode_usage(&self, response: &OllamaApiResponse) -> Option<TokenUsage> {
+        // Counts are only sent with the final message of a response.
+        if response.prompt_eval_count.is_none() && response.eval_count.is_none() {
+            return None;
+        }
+        Some(TokenUsage {
+            prompt: response.prompt_eval_count,
+            response: response.eval_count,
+        })
+    }
+
+    fn decode_stream_usage(&self, response: &OllamaApiResponse) -> Option<TokenUsage> {
+        self.decode_usage(response)
+    }
+
+    fn decode_tool_calls(&self, response: &OllamaApiResponse) -> Vec<ToolCallData> {
+        response
+            .message
+            .iter()
+            .flat_map(|message| &message.tool_calls)
+            .map(|call| {
+                ToolCallData::requested(
+                    call.function.name.clone(),
+                    call.function.arguments.clone(),
+                    None,
+                )
+            })
+            .collect()
+    }
+
+    /// Ollama streams each call whole, in the message of a single line.
+    fn decode_stream_tool_calls(&self, response: &OllamaApiResponse) -> Vec<ToolCallData> {
+        self.decode_tool_calls(response)
+    }
+}
+
+impl OllamaProvider {
+    pub fn new(config: &Configuration) -> Result<Self> {
+        Ok(Self {
+            provider: OnlineProvider::without_key(&config.provider_opts.ollama.online_opts),
+            http_client: reqwest::Client::builder().build()?,
+            memory: ChatMemory::new(config),
+        })
+    }
+}
+
+impl OllamaProvider {
+    /// Messages for one entry of the history. Calls are an assistant message listing them,
+    /// followed by a `tool` message with the result of each. Ollama matches results to calls
+    /// by position, so they carry no ids.
+    fn serialise_entry(messages: &mut Vec<serde_json::Value>, entry: HistoryEntry) {
+        match entry {
+            HistoryEntry::Message { role, text } => messages.push(json!({
+                "role": match role {
+                    ChatRole::Model => "assistant",
+                    _ => "user",
+                },
+                "content": text
+            })),
+            HistoryEntry::ToolCalls { text, calls } => {
+                let tool_calls = calls
+                    .iter()
+                    .map(|call| {
+                        json!({
+                            "function": {
+                                "name": call.name,
+                                "arguments": call.arguments
+                            }
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                messages.push(json!({
+                    "role": "assistant",
+                    "content": text.unwrap_or_default(),
+                    "tool_calls": tool_calls
+                }));
+                messages.extend(calls.iter().map(|call| {
+                    json!({
+                        "role": "tool",
+                        "tool_name": call.name,
+                        "content": call.output
+                    })
+                }));
+            }
+        }
+    }
+
+    fn build_tools(&self) -> Vec<serde_json::Value> {
+        self.memory
+            .enabled_tools()
+            .map(|tool| {
+                json!({
+                    "type": "function",
+                    "function": {
+                        "name": tool.name(),
+                        "description": tool.description(),
+                        "parameters": tool.parameters()
+                    }
+                })
+            })
+            .collect()
+    }
+}
+
+mod ollama_api_response {
+    use super::*;
+
+    #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct OllamaApiResponse {
+        pub message: Option<Message>,
+        pub error: Option<String>,
+        pub prompt_eval_count: Option<u64>,
+        pub eval_count: Option<u64>,
+    }
+
+    #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct Message {
+        pub content: String,
+        #[serde(default)]
+        pub tool_calls: Vec<ToolCall>,
+    }
+
+    #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct ToolCall {
+        pub function: Function,
+    }
+
+    #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct Function {
+        pub name: String,
+        #[serde(default)]
+        pub arguments: serde_json::Value,
+    }
+}
@@ -0,0 +1,395 @@
+use openai_api_response::{OpenAIApiResponse, OpenAIApiStreamResponse, ToolCall, Usage};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+
+use crate::{
+    APIKeyManager,
+    cli_handler::CliHandler,
+    configuration::{AuthScheme, Configuration, OpenAICompatibleProviderOpts},
+};
+
+use anyhow::Context;
+
+use super::{
+    ChatData, ChatHistoryItem, ChatMemory, ChatRole, HistoryEntry, OPENAI_PROVIDER, OnlineProvider,
+    OnlineProviderImpl, ProviderImpl, Result, TokenUsage, ToolCallData, ToolCallDelta,
+    decode_arguments, history_entries, redact,
+};
+
+const STREAM_DONE: &str = "[DONE]";
+
+/// Provider for the OpenAI chat completions API, also used for any named endpoint that
+/// speaks the same protocol (DeepSeek, Groq, vLLM, LM Studio, llama.cpp, ...).
+#[derive(Debug, Clone)]
+pub struct OpenAIProvider {
+    provider: OnlineProvider,
+    http_client: reqwest::Client,
+    memory: ChatMemory,
+    auth: AuthScheme,
+    headers: HashMap<String, String>,
+    /// Send `stream_options` to get token counts in streamed responses.
+    stream_usage: bool,
+}
+
+impl ProviderImpl for OpenAIProvider {
+    fn provider_str() -> &'static str {
+        OPENAI_PROVIDER
+    }
+
+    fn memory(&self) -> &ChatMemory {
+        &self.memory
+    }
+
+    fn memory_mut(&mut self) -> &mut ChatMemory {
+        &mut self.memory
+    }
+}
+
+impl OnlineProviderImpl for OpenAIProvider {
+    type ProviderApiResponse = OpenAIApiResponse;
+    type ProviderApiStreamResponse = OpenAIApiStreamResponse;
+
+    fn build_chat_url(&self) -> anyhow::Result<Url> {
+        reqwest::Url::parse(&self.provider.url)
+            .context("Failed to parse provider url")?
+            .join("v1/chat/completions")
+            .context("Failed to build openai url.")
+    }
+
+    fn build_chat_stream_url(&self) -> anyhow::Result<Url> {
+        self.build_chat_url()
+    }
+
+    fn build_models_url(&self) -> anyhow::Result<Url> {
+        reqwest::Url::parse(&self.provider.url)
+            .context("Failed to parse provider url")?
+            .join("v1/models")
+            .context("Failed to build openai models url.")
+    }
+
+    fn build_chat_body(
+        &self,
+        prompt: impl Into<String>,
+        turn: &[ChatHistoryItem],
+        stream: bool,
+    ) -> serde_json::Value {
+        let mut messages = Vec::new();
+        if let Some(instructions) = &self.memory.system_prompt {
+            messages.push(json!({
+                "role": "system",
+                "content": instructions
+            }));
+        }
+        let new_chat = ChatData::user(prompt.into()).into();
+        let history = self.memory.history.iter().chain([&new_chat]).chain(turn);
+        for (index, entry) in history_entries(history).into_iter().enumerate() {
+            Self::serialise_entry(&mut messages, index, entry);
+        }
+
+        let mut body = json!({
+            "model": self.provider.model,
+            "messages": messages,
+            "stream": stream
+        });
+        let tools = self.build_tools();
+        if !tools.is_empty() {
+            body["tools"] = json!(tools);
+        }
+        if stream && self.stream_usage {
+            // Ask for token counts in a final chunk, as streamed chunks otherwise carry none.
+            body["stream_options"] = json!({ "include_usage": true });
+        }
+        body
+    }
+
+    fn get_http_client(&self) -> &reqwest::Client {
+        &self.http_client
+    }
+
+    fn authorise_request(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        let request = self.headers.iter().fold(request, |request, (name, value)| {
+            request.header(name, value)
+        });
+        match (&self.auth, &self.provider.api_key) {
+            (AuthScheme::Bearer, Some(api_key)) => request.bearer_auth(api_key),
+            (AuthScheme::Header(header), Some(api_key)) => {
+                redact::secret_header(request, header, api_key)
+            }
+            _ => request,
+        }
+    }
+
+    fn is_stream_done(&self, data: &str) -> bool {
+        data.trim() == STREAM_DONE
+    }
+
+    fn decode_llm_response(&self, response: OpenAIApiResponse) -> anyhow::Result<String> {
+        let text = response
+            .choices
+            .into_iter()
+            .next()
+            .context("OpenAI response has no choices")?
+            .message
+            .content
+            .unwrap_or_default();
+        Ok(text)
+    }
+
+    fn decode_llm_stream_response(
+        &self,
+        response: Self::ProviderApiStreamResponse,
+    ) -> anyhow::Result<String> {
+        // Role announcements and the final `finish_reason` chunk carry no content.
+        Ok(response
+            .choices
+            .into_iter()
+            .next()
+            .and_then(|choice| choice.delta.content)
+            .unwrap_or_default())
+    }
+
+    fn context_window(&self) -> Option<u64> {
+        self.provider.context_window
+    }
+
+    fn decode_tool_calls(&self, response: &OpenAIApiResponse) -> Vec<ToolCallData> {
+        response
+            .choices
+            .first()
+            .and_then(|choice| choice.message.tool_calls.as_ref())
+            .into_iter()
+            .flatten()
+            .map(ToolCall::requested)
+            .collect()
+    }
+
+    fn decode_stream_tool_call_deltas(
+        &self,
+        response: &OpenAIApiStreamResponse,
+    ) -> Vec<ToolCallDelta> {
+        response
+            .choices
+            .first()
+            .and_then(|choice| choice.delta.tool_calls.as_ref())
+            .into_iter()
+            .flatten()
+            .map(|call| ToolCallDelta {
+                index: call.index,
+                id: call.id.clone(),
+                name: call.function.name.clone(),
+                arguments: call.function.arguments.clone().unwrap_or_default(),
+            })
+            .collect()
+    }
+
+    fn decode_usage(&self, response: &OpenAIApiResponse) -> Option<TokenUsage> {
+        response.usage.as_ref().map(Usage::token_usage)
+    }
+
+    fn decode_stream_usage(&self, response: &OpenAIApiStreamResponse) -> Option<TokenUsage> {
+        response.usage.as_ref().map(Usage::token_usage)
+    }
+}
+
+impl OpenAIProvider {
+    pub fn new(
+        config: &Configuration,
+        api_key_manager: &APIKeyManager,
+        cli_handler: Option<&CliHandler>,
+    ) -> Result<Self> {
+        Ok(Self {
+            provider: OnlineProvider::new(
+                OpenAIProvider::provider_str(),
+                &config.provider_opts.openai.online_opts,
+                api_key_manager,
+                cli_handler,
+            )?,
+            http_client: reqwest::Client::builder().build()?,
+            memory: ChatMemory::new(config),
+            auth: AuthScheme::Bearer,
+            headers: HashMap::new(),
+            stream_usage: true,
+        })
+    }
+
+    /// Build a provider for a named `openai_compatible` endpoint. The api key is stored in the
+    /// keyring under the endpoint name.
+    pub fn compatible(
+        name: &str,
+        opts: &OpenAICompatibleProviderOpts,
+        config: &Configuration,
+        api_key_manager: &APIKeyManager,
+        cli_handler: Option<&CliHandler>,
+    ) -> Result<Self> {
+        let provider = match opts.auth {
+            AuthScheme::None => OnlineProvider::without_key(&opts.online_opts),
+            _ => OnlineProvider::new(name, &opts.online_opts, api_key_manager, cli_handler)?,
+        };
+        Ok(Self {
+            provider,
+            http_client: reqwest::Client::builder().build()?,
+            memory: ChatMemory::new(config),
+            auth: opts.auth.clone(),
+            headers: opts.headers.clone(),
+            stream_usage: opts.stream_usage,
+        })
+    }
+}
+
+impl OpenAIProvider {
+    /// Messages for one entry of the history. Calls are an assistant message listing them,
+    /// followed by a `tool` message with the result of each.
+    fn serialise_entry(messages: &mut Vec<serde_json::Value>, index: usize, entry: HistoryEntry) {
+        match entry {
+            HistoryEntry::Message { role, text } => messages.push(json!({
+                "role": match role {
+                    ChatRole::Model => "assistant",
+                    _ => "user",
+                },
+                "content": text
+            })),
+            HistoryEntry::ToolCalls { text, calls } => {
+                let tool_calls = calls
+                    .iter()
+                    .enumerate()
+                    .map(|(position, call)| {
+                        json!({
+                            "id": call.id_or_position(index, position),
+                            "type": "function",
+                            "function": {
+                                "name": call.name,
+                                "arguments": call.arguments.to_string()
+                            }
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                messages.push(json!({
+                    "role": "assistant",
+                    "content": text,
+                    "tool_calls": tool_calls
+                }));
+                messages.extend(calls.iter().enumerate().map(|(position, call)| {
+                    json!({
+                        "role": "tool",
+                        "tool_call_id": call.id_or_position(index, position),
+                        "content": call.output
+                    })
+                }));
+            }
+        }
+    }
+
+    fn build_tools(&self) -> Vec<serde_json::Value> {
+        self.memory
+            .enabled_tools()
+            .map(|tool| {
+                json!({
+                    "type": "function",
+                    "function": {
+                        "name": tool.name(),
+                        "description": tool.description(),
+                        "parameters": tool.parameters()
+                    }
+                })
+            })
+            .collect()
+    }
+}
+
+mod openai_api_response {
+    use super::*;
+
+    #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct OpenAIApiResponse {
+        pub choices: Vec<Choice>,
+        pub usage: Option<Usage>,
+    }
+
+    #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct Usage {
+        pub prompt_tokens: Option<u64>,
+        pub completion_tokens: Option<u64>,
+    }
+
+    impl Usage {
+        pub fn token_usage(&self) -> TokenUsage {
+            TokenUsage {
+                prompt: self.prompt_tokens,
+                response: self.completion_tokens,
+            }
+        }
+    }
+
+    #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct Choice {
+        pub message: Message,
+    }
+
+    #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct Message {
+        pub content: Option<String>,
+        pub tool_calls: Option<Vec<ToolCall>>,
+    }
+
+    #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct ToolCall {
+        pub id: String,
+        pub function: Function,
+    }
+
+    /// A function call, with its arguments as a JSON encoded string.
+    #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct Function {
+        pub name: String,
+        #[serde(default)]
+        pub arguments: String,
+    }
+
+    impl ToolCall {
+        pub fn requested(&self) -> ToolCallData {
+            ToolCallData {
+                id: Some(self.id.clone()),
+                ..ToolCallData::requested(
+                    self.function.name.clone(),
+                    decode_arguments(&self.function.arguments),
+                    None,
+                )
+            }
+        }
+    }
+
+    #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct OpenAIApiStreamResponse {
+        pub choices: Vec<StreamChoice>,
+        pub usage: Option<Usage>,
+    }
+
+    #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct StreamChoice {
+        pub delta: Delta,
+    }
+
+    #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct Delta {
+        pub content: Option<String>,
+        pub tool_calls: Option<Vec<ToolCallChunk>>,
+    }
+
+    /// Part of a streamed tool call. Only the first part of a call carries its id and name.
+    #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct ToolCallChunk {
+        #[serde(default)]
+        pub index: usize,
+        pub id: Option<String>,
+        #[serde(default)]
+        pub function: FunctionChunk,
+    }
+
+    #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct FunctionChunk {
+        pub name: Option<String>,
+        pub arguments: Option<String>,
+    }
+}
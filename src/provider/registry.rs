@@ -0,0 +1,160 @@
+use std::collections::BTreeMap;
+
+use crate::{
+    APIKeyManager,
+    cli_handler::CliHandler,
+    configuration::{AuthScheme, Configuration},
+};
+
+use super::{
+    CLAUDE_PROVIDER, Error, GEMINI_PROVIDER, LlmProvider, OLLAMA_PROVIDER, OPENAI_PROVIDER,
+    Provider, Result, claude::ClaudeProvider, gemini::GeminiProvider, ollama::OllamaProvider,
+    openai::OpenAIProvider,
+};
+
+trait ProviderFactoryFn:
+    Fn(&Configuration, &APIKeyManager, Option<&CliHandler>) -> Result<Box<dyn LlmProvider>> + 'static
+{
+}
+
+impl<F> ProviderFactoryFn for F where
+    F: Fn(&Configuration, &APIKeyManager, Option<&CliHandler>) -> Result<Box<dyn LlmProvider>>
+        + 'static
+{
+}
+
+type ProviderFactory = Box<dyn ProviderFactoryFn>;
+
+/// Factories for every provider selectable with `provider = "<name>"` in `llcli.toml`.
+///
+/// New backends are added by registering a factory in [`ProviderRegistry::new`].
+pub struct ProviderRegistry {
+    factories: BTreeMap<String, RegisteredProvider>,
+}
+
+struct RegisteredProvider {
+    factory: ProviderFactory,
+    needs_api_key: bool,
+}
+
+impl ProviderRegistry {
+    pub fn new(config: &Configuration) -> Result<Self> {
+        let mut registry = Self {
+            factories: BTreeMap::new(),
+        };
+
+        registry.register(GEMINI_PROVIDER, |config, api_key_manager, cli_handler| {
+            Ok(Box::new(GeminiProvider::new(
+                config,
+                api_key_manager,
+                cli_handler,
+            )?))
+        });
+        registry.register(OPENAI_PROVIDER, |config, api_key_manager, cli_handler| {
+            Ok(Box::new(OpenAIProvider::new(
+                config,
+                api_key_manager,
+                cli_handler,
+            )?))
+        });
+        registry.register(CLAUDE_PROVIDER, |config, api_key_manager, cli_handler| {
+            Ok(Box::new(ClaudeProvider::new(
+                config,
+                api_key_manager,
+                cli_handler,
+            )?))
+        });
+        registry.register_keyless(OLLAMA_PROVIDER, |config, _, _| {
+            Ok(Box::new(OllamaProvider::new(config)?))
+        });
+
+        for (name, opts) in &config.provider_opts.openai_compatible {
+            if registry.factories.contains_key(name) {
+                return Err(Error::ProviderNameTaken { name: name.clone() });
+            }
+            let needs_api_key = opts.auth != AuthScheme::None;
+            let (name, opts) = (name.clone(), opts.clone());
+            registry.insert(
+                name.clone(),
+                needs_api_key,
+                move |config, api_key_manager, cli_handler| {
+                    Ok(Box::new(OpenAIProvider::compatible(
+                        &name,
+                        &opts,
+                        config,
+                        api_key_manager,
+                        cli_handler,
+                    )?))
+                },
+            );
+        }
+
+        Ok(registry)
+    }
+
+    fn register(&mut self, name: impl Into<String>, factory: impl ProviderFactoryFn) {
+        self.insert(name, true, factory);
+    }
+
+    /// Register a provider that never authenticates, such as a local model server.
+    fn register_keyless(&mut self, name: impl Into<String>, factory: impl ProviderFactoryFn) {
+        self.insert(name, false, factory);
+    }
+
+    fn insert(
+        &mut self,
+        name: impl Into<String>,
+        needs_api_key: bool,
+        factory: impl ProviderFactoryFn,
+    ) {
+        self.factories.insert(
+            name.into(),
+            RegisteredProvider {
+                factory: Box::new(factory),
+                needs_api_key,
+            },
+        );
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.factories.keys().map(String::as_str)
+    }
+
+    /// Names of the registered providers that authenticate with an api key.
+    pub fn api_key_names(&self) -> impl Iterator<Item = &str> {
+        self.factories
+            .iter()
+            .filter(|(_, provider)| provider.needs_api_key)
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// Fail with [`Error::UnknownProvider`] unless `name` is registered.
+    pub fn ensure_known(&self, name: &str) -> Result<()> {
+        if self.factories.contains_key(name) {
+            Ok(())
+        } else {
+            Err(self.unknown(name))
+        }
+    }
+
+    fn unknown(&self, name: &str) -> Error {
+        Error::UnknownProvider {
+            name: name.to_string(),
+            available: self.names().collect::<Vec<_>>().join(", "),
+        }
+    }
+
+    pub fn build(
+        &self,
+        name: &str,
+        config: &Configuration,
+        api_key_manager: &APIKeyManager,
+        cli_handler: Option<&CliHandler>,
+    ) -> Result<Provider> {
+        let provider = self.factories.get(name).ok_or_else(|| self.unknown(name))?;
+        Ok(Provider {
+            inner: (provider.factory)(config, api_key_manager, cli_handler)?,
+            compaction: config.compaction.clone(),
+        })
+    }
+}
@@ -0,0 +1,123 @@
+use std::fmt;
+
+use super::ChatHistoryItem;
+
+/// Token counts a provider reported for one exchange. Some providers report the prompt and
+/// response counts in different stream events, so either may be missing.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) struct TokenUsage {
+    pub(crate) prompt: Option<u64>,
+    pub(crate) response: Option<u64>,
+}
+
+impl TokenUsage {
+    /// Take any counts `other` reports, keeping ours where it has none.
+    pub(crate) fn merge(&mut self, other: TokenUsage) {
+        self.prompt = other.prompt.or(self.prompt);
+        self.response = other.response.or(self.response);
+    }
+}
+
+/// Tokens a request would use, as counted by the provider or estimated locally.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TokenCount {
+    pub tokens: u64,
+    pub estimated: bool,
+}
+
+impl fmt::Display for TokenCount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let approx = if self.estimated { "~" } else { "" };
+        write!(f, "{approx}{}", format_count(self.tokens))
+    }
+}
+
+/// Token counts for the latest exchange against the model's context window, for display.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TokenReport {
+    pub prompt: u64,
+    pub response: u64,
+    /// Some counts were estimated locally because the provider did not report them.
+    pub estimated: bool,
+    pub context_window: Option<u64>,
+}
+
+impl TokenReport {
+    pub fn total(&self) -> u64 {
+        self.prompt + self.response
+    }
+}
+
+impl fmt::Display for TokenReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let approx = if self.estimated { "~" } else { "" };
+        write!(
+            f,
+            "Tokens: {approx}{} prompt + {approx}{} response = {approx}{}",
+            format_count(self.prompt),
+            format_count(self.response),
+            format_count(self.total())
+        )?;
+        if let Some(limit) = self.context_window {
+            write!(
+                f,
+                " of {} ({:.0}%)",
+                format_count(limit),
+                self.total() as f64 * 100.0 / limit as f64
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Rough token count for providers that cannot count for us. Four characters a token is
+/// close enough for English text and code to judge how full the context window is.
+pub(crate) fn estimate_tokens(text: &str) -> u64 {
+    (text.chars().count() as u64).div_ceil(4)
+}
+
+/// Rough token count of an item in the conversation.
+pub(crate) fn estimate_item_tokens(item: &ChatHistoryItem) -> u64 {
+    match item {
+        ChatHistoryItem::Chat(chat) => estimate_tokens(&chat.text),
+        ChatHistoryItem::FileUpload(file) => estimate_tokens(&file.text),
+        ChatHistoryItem::ToolCall(call) => {
+            estimate_tokens(&call.arguments.to_string()) + estimate_tokens(&call.output)
+        }
+    }
+}
+
+/// Context window of well known models, matched by prefix. Set `context_window` in a
+/// provider's config for anything not listed.
+pub(crate) fn default_context_window(model: &str) -> Option<u64> {
+    const WINDOWS: &[(&str, u64)] = &[
+        ("gemini-1.5-pro", 2_097_152),
+        ("gemini", 1_048_576),
+        ("gpt-4.1", 1_047_576),
+        ("gpt-5", 400_000),
+        ("gpt-4o", 128_000),
+        ("gpt-4-turbo", 128_000),
+        ("gpt-3.5-turbo", 16_385),
+        ("o1", 200_000),
+        ("o3", 200_000),
+        ("o4", 200_000),
+        ("claude", 200_000),
+    ];
+    WINDOWS
+        .iter()
+        .find(|(prefix, _)| model.starts_with(prefix))
+        .map(|(_, window)| *window)
+}
+
+/// A count with thousands separators, e.g. `12,345`.
+pub fn format_count(count: u64) -> String {
+    let digits = count.to_string();
+    let mut formatted = String::with_capacity(digits.len() + digits.len() / 3);
+    for (index, digit) in digits.chars().enumerate() {
+        if index > 0 && (digits.len() - index).is_multiple_of(3) {
+            formatted.push(',');
+        }
+        formatted.push(digit);
+    }
+    formatted
+}
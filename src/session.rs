@@ -0,0 +1,167 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::trace;
+
+use crate::provider::ChatMemory;
+
+const SESSION_EXTENSION: &str = "json";
+
+/// A saved conversation that can be resumed with any provider.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Session {
+    /// Provider the session was last used with.
+    pub provider: String,
+    #[serde(flatten)]
+    pub memory: ChatMemory,
+}
+
+pub struct SessionSummary {
+    pub name: String,
+    pub modified: SystemTime,
+}
+
+/// Sessions stored as JSON files in the user data dir.
+pub struct SessionStore {
+    dir: PathBuf,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Could not determine the user data directory.")]
+    NoDataDir,
+    #[error(
+        "Invalid session name {0:?}. Use letters, numbers, '-', '_' and '.', not starting with '.'."
+    )]
+    InvalidName(String),
+    #[error("No session named {0:?}. Run `llcli_rs sessions list` to see saved sessions.")]
+    NotFound(String),
+    #[error("No saved sessions to continue.")]
+    NoSessions,
+    #[error("Failed to access session file {path:?}.")]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("Session file {path:?} is not a valid session.")]
+    Invalid {
+        path: PathBuf,
+        #[source]
+        source: serde_json::Error,
+    },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl SessionStore {
+    pub fn new() -> Result<Self> {
+        let dir = dirs::data_dir()
+            .ok_or(Error::NoDataDir)?
+            .join("llcli_rs")
+            .join("sessions");
+        Ok(Self { dir })
+    }
+
+    /// Saved sessions, most recently used first.
+    pub fn list(&self) -> Result<Vec<SessionSummary>> {
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(source) => return Err(io_error(&self.dir, source)),
+        };
+        let mut sessions = entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let path = entry.path();
+                if path.extension()? != SESSION_EXTENSION {
+                    return None;
+                }
+                Some(SessionSummary {
+                    name: path.file_stem()?.to_str()?.to_string(),
+                    modified: entry.metadata().and_then(|meta| meta.modified()).ok()?,
+                })
+            })
+            .collect::<Vec<_>>();
+        sessions.sort_by_key(|session| std::cmp::Reverse(session.modified));
+        Ok(sessions)
+    }
+
+    /// Name of the most recently used session.
+    pub fn latest(&self) -> Result<String> {
+        self.list()?
+            .into_iter()
+            .next()
+            .map(|session| session.name)
+            .ok_or(Error::NoSessions)
+    }
+
+    pub fn exists(&self, name: &str) -> Result<bool> {
+        Ok(self.path(name)?.exists())
+    }
+
+    pub fn load(&self, name: &str) -> Result<Session> {
+        let path = self.path(name)?;
+        let contents = std::fs::read_to_string(&path).map_err(|source| {
+            if source.kind() == std::io::ErrorKind::NotFound {
+                Error::NotFound(name.to_string())
+            } else {
+                io_error(&path, source)
+            }
+        })?;
+        serde_json::from_str(&contents).map_err(|source| Error::Invalid { path, source })
+    }
+
+    pub fn save(&self, name: &str, session: &Session) -> Result<()> {
+        let path = self.path(name)?;
+        trace!("Saving session to {:?}", path);
+        std::fs::create_dir_all(&self.dir).map_err(|source| io_error(&self.dir, source))?;
+        let contents = serde_json::to_string_pretty(session).map_err(|source| Error::Invalid {
+            path: path.clone(),
+            source,
+        })?;
+        std::fs::write(&path, contents).map_err(|source| io_error(&path, source))
+    }
+
+    pub fn delete(&self, name: &str) -> Result<()> {
+        let path = self.path(name)?;
+        std::fs::remove_file(&path).map_err(|source| {
+            if source.kind() == std::io::ErrorKind::NotFound {
+                Error::NotFound(name.to_string())
+            } else {
+                io_error(&path, source)
+            }
+        })
+    }
+
+    fn path(&self, name: &str) -> Result<PathBuf> {
+        let valid = !name.is_empty()
+            && !name.starts_with('.')
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+        if !valid {
+            return Err(Error::InvalidName(name.to_string()));
+        }
+        Ok(self.dir.join(format!("{name}.{SESSION_EXTENSION}")))
+    }
+}
+
+fn io_error(path: &Path, source: std::io::Error) -> Error {
+    Error::Io {
+        path: path.to_path_buf(),
+        source,
+    }
+}
+
+/// Name for a session the user did not name, unique to the second it was started.
+pub fn generate_name() -> String {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+    format!("chat-{seconds}")
+}
@@ -0,0 +1,33 @@
+use anyhow::Context;
+use futures_util::{FutureExt, future::BoxFuture};
+use serde_json::json;
+use time::{OffsetDateTime, format_description::well_known::Rfc3339};
+
+use super::Tool;
+
+/// The current date and time, which models cannot know from their training data.
+#[derive(Debug)]
+pub(super) struct CurrentTime;
+
+impl Tool for CurrentTime {
+    fn name(&self) -> &str {
+        "current_time"
+    }
+
+    fn description(&self) -> &str {
+        "Get the current date and time in UTC, as an RFC 3339 timestamp."
+    }
+
+    fn parameters(&self) -> serde_json::Value {
+        json!({ "type": "object", "properties": {} })
+    }
+
+    fn call(&self, _arguments: serde_json::Value) -> BoxFuture<'_, anyhow::Result<String>> {
+        async {
+            OffsetDateTime::now_utc()
+                .format(&Rfc3339)
+                .context("Failed to format the current time.")
+        }
+        .boxed()
+    }
+}
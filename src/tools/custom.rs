@@ -0,0 +1,119 @@
+use std::process::Stdio;
+
+use anyhow::Context;
+use futures_util::{FutureExt, future::BoxFuture};
+use serde_json::json;
+use tokio::io::AsyncWriteExt;
+
+use crate::configuration::{ArgumentInput, CustomToolOptions};
+
+use super::Tool;
+
+const ARGUMENT_ENV_PREFIX: &str = "LLCLI_ARG_";
+
+/// A tool declared in the config, running its command with the model's arguments.
+#[derive(Debug)]
+pub(super) struct CommandTool {
+    options: CustomToolOptions,
+}
+
+impl CommandTool {
+    pub(super) fn new(options: CustomToolOptions) -> Self {
+        Self { options }
+    }
+}
+
+impl Tool for CommandTool {
+    fn name(&self) -> &str {
+        &self.options.name
+    }
+
+    fn description(&self) -> &str {
+        &self.options.description
+    }
+
+    fn parameters(&self) -> serde_json::Value {
+        self.options
+            .parameters
+            .clone()
+            .unwrap_or_else(|| json!({ "type": "object", "properties": {} }))
+    }
+
+    fn call(&self, arguments: serde_json::Value) -> BoxFuture<'_, anyhow::Result<String>> {
+        async move {
+            let mut command = tokio::process::Command::new("sh");
+            command
+                .arg("-c")
+                .arg(&self.options.command)
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                // Killed if the response is stopped while the command runs.
+                .kill_on_drop(true);
+            match self.options.input {
+                ArgumentInput::Stdin => {
+                    command.stdin(Stdio::piped());
+                }
+                ArgumentInput::Env => {
+                    command.stdin(Stdio::null()).envs(argument_env(&arguments));
+                }
+            }
+
+            let mut child = command
+                .spawn()
+                .with_context(|| format!("Failed to run {}.", self.options.command))?;
+            if let Some(mut stdin) = child.stdin.take() {
+                // Commands that do not need the arguments may exit without reading them.
+                let written = stdin.write_all(arguments.to_string().as_bytes()).await;
+                if let Err(err) = written
+                    && err.kind() != std::io::ErrorKind::BrokenPipe
+                {
+                    return Err(err).context("Failed to write the arguments to the command.");
+                }
+            }
+            let output = child
+                .wait_with_output()
+                .await
+                .with_context(|| format!("Failed to run {}.", self.options.command))?;
+            if !output.status.success() {
+                anyhow::bail!(
+                    "{}: {}",
+                    output.status,
+                    String::from_utf8_lossy(&output.stderr).trim_end()
+                );
+            }
+            Ok(String::from_utf8_lossy(&output.stdout)
+                .trim_end()
+                .to_string())
+        }
+        .boxed()
+    }
+
+    fn side_effect(&self, _arguments: &serde_json::Value) -> Option<String> {
+        self.options
+            .confirm
+            .then(|| format!("run `{}`", self.options.command))
+    }
+}
+
+/// `LLCLI_ARG_<NAME>` for each argument. Strings are passed as they are, anything else as JSON.
+fn argument_env(arguments: &serde_json::Value) -> Vec<(String, String)> {
+    arguments
+        .as_object()
+        .into_iter()
+        .flatten()
+        .map(|(name, value)| {
+            let name = name
+                .chars()
+                .map(|c| match c.is_ascii_alphanumeric() {
+                    true => c.to_ascii_uppercase(),
+                    false => '_',
+                })
+                .collect::<String>();
+            let value = match value {
+                serde_json::Value::String(value) => value.clone(),
+                value => value.to_string(),
+            };
+            (format!("{ARGUMENT_ENV_PREFIX}{name}"), value)
+        })
+        .collect()
+}
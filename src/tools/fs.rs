@@ -0,0 +1,414 @@
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::Context;
+use futures_util::{FutureExt, future::BoxFuture};
+use serde::Deserialize;
+use serde_json::json;
+
+use super::Tool;
+
+/// Files larger than this are not read, matching what can be attached to a conversation.
+const MAX_FILE_BYTES: u64 = 256 * 1024;
+/// How much of a file is checked for NUL bytes when deciding whether it is binary.
+const BINARY_SNIFF_BYTES: usize = 8000;
+/// Matching lines returned by a single grep.
+const MAX_MATCHES: usize = 200;
+
+/// Reads a text file in the workspace.
+#[derive(Debug)]
+pub(super) struct ReadFile;
+
+#[derive(Deserialize)]
+struct FileArguments {
+    path: String,
+}
+
+impl Tool for ReadFile {
+    fn name(&self) -> &str {
+        "read_file"
+    }
+
+    fn description(&self) -> &str {
+        "Read a text file in the workspace. Paths are relative to the workspace root."
+    }
+
+    fn parameters(&self) -> serde_json::Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "path": { "type": "string", "description": "File to read." }
+            },
+            "required": ["path"]
+        })
+    }
+
+    fn call(&self, arguments: serde_json::Value) -> BoxFuture<'_, anyhow::Result<String>> {
+        async move {
+            let arguments = parse::<FileArguments>(self, arguments)?;
+            let path = workspace_path(&arguments.path)?;
+            read_text(&path)?.with_context(|| format!("{} is binary.", path.display()))
+        }
+        .boxed()
+    }
+}
+
+/// Lists a directory in the workspace, skipping anything git ignores.
+#[derive(Debug)]
+pub(super) struct ListDirectory;
+
+#[derive(Deserialize)]
+struct DirectoryArguments {
+    #[serde(default)]
+    path: Option<String>,
+}
+
+impl Tool for ListDirectory {
+    fn name(&self) -> &str {
+        "list_directory"
+    }
+
+    fn description(&self) -> &str {
+        "List the files and directories in a workspace directory, skipping anything git ignores. \
+        Directories end with a slash."
+    }
+
+    fn parameters(&self) -> serde_json::Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "path": {
+                    "type": "string",
+                    "description": "Directory to list, defaults to the workspace root."
+                }
+            }
+        })
+    }
+
+    fn call(&self, arguments: serde_json::Value) -> BoxFuture<'_, anyhow::Result<String>> {
+        async move {
+            let arguments = parse::<DirectoryArguments>(self, arguments)?;
+            let dir = workspace_path(arguments.path.as_deref().unwrap_or("."))?;
+            if !dir.is_dir() {
+                anyhow::bail!("{} is not a directory.", dir.display());
+            }
+            let entries = ignore::WalkBuilder::new(&dir)
+                .max_depth(Some(1))
+                .sort_by_file_name(|a, b| a.cmp(b))
+                .build()
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| entry.depth() == 1)
+                .map(|entry| {
+                    let name = entry.file_name().to_string_lossy();
+                    match entry
+                        .file_type()
+                        .is_some_and(|file_type| file_type.is_dir())
+                    {
+                        true => format!("{name}/"),
+                        false => name.into_owned(),
+                    }
+                })
+                .collect::<Vec<_>>();
+            Ok(match entries.is_empty() {
+                true => "The directory is empty.".to_string(),
+                false => entries.join("\n"),
+            })
+        }
+        .boxed()
+    }
+}
+
+/// Searches the text files in the workspace with a regular expression.
+#[derive(Debug)]
+pub(super) struct Grep;
+
+#[derive(Deserialize)]
+struct GrepArguments {
+    pattern: String,
+    #[serde(default)]
+    path: Option<String>,
+}
+
+impl Tool for Grep {
+    fn name(&self) -> &str {
+        "grep"
+    }
+
+    fn description(&self) -> &str {
+        "Search the text files in the workspace for lines matching a regular expression, \
+        skipping anything git ignores. Returns `path:line: text` for each match."
+    }
+
+    fn parameters(&self) -> serde_json::Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "pattern": { "type": "string", "description": "Regular expression to search for." },
+                "path": {
+                    "type": "string",
+                    "description": "File or directory to search, defaults to the workspace root."
+                }
+            },
+            "required": ["pattern"]
+        })
+    }
+
+    fn call(&self, arguments: serde_json::Value) -> BoxFuture<'_, anyhow::Result<String>> {
+        async move {
+            let arguments = parse::<GrepArguments>(self, arguments)?;
+            let regex = regex::Regex::new(&arguments.pattern)
+                .with_context(|| format!("Invalid regular expression {}.", arguments.pattern))?;
+            let root = workspace_path(arguments.path.as_deref().unwrap_or("."))?;
+            tokio::task::spawn_blocking(move || grep(&regex, &root))
+                .await
+                .context("Failed to search the workspace.")?
+        }
+        .boxed()
+    }
+}
+
+fn grep(regex: &regex::Regex, root: &Path) -> anyhow::Result<String> {
+    let workspace = std::env::current_dir()
+        .and_then(std::fs::canonicalize)
+        .context("Failed to get current directory.")?;
+    let files = ignore::WalkBuilder::new(root)
+        .sort_by_file_name(|a, b| a.cmp(b))
+        .build()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            entry
+                .file_type()
+                .is_some_and(|file_type| file_type.is_file())
+        });
+
+    let mut matches = Vec::new();
+    for file in files {
+        let Ok(Some(text)) = read_text(file.path()) else {
+            continue;
+        };
+        let path = file.path().strip_prefix(&workspace).unwrap_or(file.path());
+        for (number, line) in text.lines().enumerate() {
+            if !regex.is_match(line) {
+                continue;
+            }
+            if matches.len() == MAX_MATCHES {
+                matches.push(format!("[stopped after {MAX_MATCHES} matches]"));
+                return Ok(matches.join("\n"));
+            }
+            matches.push(format!(
+                "{}:{}: {}",
+                path.display(),
+                number + 1,
+                line.trim()
+            ));
+        }
+    }
+    Ok(match matches.is_empty() {
+        true => "No matches.".to_string(),
+        false => matches.join("\n"),
+    })
+}
+
+/// Writes a file in the workspace, replacing it if it exists.
+#[derive(Debug)]
+pub(super) struct WriteFile;
+
+#[derive(Deserialize)]
+struct WriteFileArguments {
+    path: String,
+    content: String,
+}
+
+impl Tool for WriteFile {
+    fn name(&self) -> &str {
+        "write_file"
+    }
+
+    fn description(&self) -> &str {
+        "Write a file in the workspace, creating it and its directories or replacing its contents."
+    }
+
+    fn parameters(&self) -> serde_json::Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "path": { "type": "string", "description": "File to write." },
+                "content": { "type": "string", "description": "The complete new contents." }
+            },
+            "required": ["path", "content"]
+        })
+    }
+
+    fn call(&self, arguments: serde_json::Value) -> BoxFuture<'_, anyhow::Result<String>> {
+        async move {
+            let arguments = parse::<WriteFileArguments>(self, arguments)?;
+            let path = workspace_path(&arguments.path)?;
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)
+                    .with_context(|| format!("Failed to create {}.", parent.display()))?;
+            }
+            std::fs::write(&path, &arguments.content)
+                .with_context(|| format!("Failed to write {}.", path.display()))?;
+            Ok(format!(
+                "Wrote {} bytes to {}.",
+                arguments.content.len(),
+                arguments.path
+            ))
+        }
+        .boxed()
+    }
+
+    fn side_effect(&self, arguments: &serde_json::Value) -> Option<String> {
+        let path = arguments["path"].as_str().unwrap_or_default();
+        let bytes = arguments["content"]
+            .as_str()
+            .map(str::len)
+            .unwrap_or_default();
+        Some(format!("write {bytes} bytes to {path}"))
+    }
+}
+
+fn parse<T: serde::de::DeserializeOwned>(
+    tool: &dyn Tool,
+    arguments: serde_json::Value,
+) -> anyhow::Result<T> {
+    serde_json::from_value(arguments)
+        .with_context(|| format!("Invalid arguments for {}.", tool.name()))
+}
+
+/// Resolve `path` against the workspace, the current directory, refusing paths outside it,
+/// including those reached through symlinks.
+fn workspace_path(path: &str) -> anyhow::Result<PathBuf> {
+    let workspace = std::env::current_dir()
+        .and_then(std::fs::canonicalize)
+        .context("Failed to get current directory.")?;
+    resolve_in(&workspace, path)
+}
+
+/// Resolve `path` against `workspace`, which must already be canonical.
+fn resolve_in(workspace: &Path, path: &str) -> anyhow::Result<PathBuf> {
+    let mut resolved = PathBuf::new();
+    for component in workspace.join(path).components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                resolved.pop();
+            }
+            component => resolved.push(component),
+        }
+    }
+    // Files about to be written do not exist yet, so symlinks are resolved in the longest part
+    // of the path that does.
+    let mut existing = resolved.as_path();
+    let mut missing = Vec::new();
+    let mut resolved = loop {
+        match std::fs::canonicalize(existing) {
+            Ok(canonical) => break canonical,
+            Err(_) => {
+                missing.extend(existing.file_name());
+                existing = existing
+                    .parent()
+                    .with_context(|| format!("Failed to resolve {path}."))?;
+            }
+        }
+    };
+    resolved.extend(missing.into_iter().rev());
+    if !resolved.starts_with(workspace) {
+        anyhow::bail!("{path} is outside the workspace.");
+    }
+    Ok(resolved)
+}
+
+/// Contents of a text file, or `None` when it is binary.
+fn read_text(path: &Path) -> anyhow::Result<Option<String>> {
+    let size = std::fs::metadata(path)
+        .with_context(|| format!("Failed to read metadata of {}.", path.display()))?
+        .len();
+    if size > MAX_FILE_BYTES {
+        anyhow::bail!("{} is larger than {MAX_FILE_BYTES} bytes.", path.display());
+    }
+    let contents = std::fs::read(path)
+        .with_context(|| format!("Failed to read file contents of {}.", path.display()))?;
+    Ok(String::from_utf8(contents).ok().filter(|text| {
+        !text
+            .as_bytes()
+            .iter()
+            .take(BINARY_SNIFF_BYTES)
+            .any(|b| *b == 0)
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An empty canonical directory to use as the workspace, unique to the test.
+    fn workspace(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("llcli_fs_{}_{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("src")).unwrap();
+        std::fs::canonicalize(dir).unwrap()
+    }
+
+    #[test]
+    fn resolves_paths_inside_the_workspace() {
+        let workspace = workspace("inside");
+
+        assert_eq!(
+            resolve_in(&workspace, "src").unwrap(),
+            workspace.join("src")
+        );
+        assert_eq!(
+            resolve_in(&workspace, "./src/../src/new.rs").unwrap(),
+            workspace.join("src/new.rs")
+        );
+        // Files about to be written may be in directories that do not exist yet.
+        assert_eq!(
+            resolve_in(&workspace, "docs/guide/intro.md").unwrap(),
+            workspace.join("docs/guide/intro.md")
+        );
+    }
+
+    #[test]
+    fn refuses_parent_directory_escapes() {
+        let workspace = workspace("parent");
+
+        for path in ["..", "../etc/passwd", "src/../../outside", "src/../.."] {
+            assert!(resolve_in(&workspace, path).is_err(), "{path} was allowed");
+        }
+    }
+
+    #[test]
+    fn refuses_absolute_paths_outside_the_workspace() {
+        let workspace = workspace("absolute");
+
+        assert!(resolve_in(&workspace, "/etc/passwd").is_err());
+        assert!(resolve_in(&workspace, &workspace.join("src").to_string_lossy()).is_ok());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn refuses_symlink_escapes() {
+        let workspace = workspace("symlink");
+        let outside = workspace.with_extension("outside");
+        std::fs::create_dir_all(&outside).unwrap();
+        std::fs::write(outside.join("secret"), "secret").unwrap();
+        std::os::unix::fs::symlink(&outside, workspace.join("link")).unwrap();
+        std::os::unix::fs::symlink(outside.join("secret"), workspace.join("secret")).unwrap();
+
+        for path in ["link", "link/secret", "link/new.txt", "secret"] {
+            assert!(resolve_in(&workspace, path).is_err(), "{path} was allowed");
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn follows_symlinks_that_stay_inside() {
+        let workspace = workspace("inner_symlink");
+        std::os::unix::fs::symlink(workspace.join("src"), workspace.join("source")).unwrap();
+
+        assert_eq!(
+            resolve_in(&workspace, "source/lib.rs").unwrap(),
+            workspace.join("src/lib.rs")
+        );
+    }
+}
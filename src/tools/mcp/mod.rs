@@ -0,0 +1,280 @@
+mod transport;
+
+use std::sync::{
+    Arc,
+    atomic::{AtomicU64, Ordering},
+};
+use std::time::Duration;
+
+use anyhow::Context;
+use futures_util::{FutureExt, future::BoxFuture};
+use serde::Deserialize;
+use serde_json::{Value, json};
+
+use crate::configuration::McpServerOptions;
+
+use super::Tool;
+use transport::Transport;
+
+/// Protocol revision requested when initialising; servers answer with the one they speak.
+const PROTOCOL_VERSION: &str = "2025-03-26";
+/// How long a server has to start and list its tools.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long a tool call may run before it is given up on.
+const CALL_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Start or reach the MCP server `name` and return its tools, named `<name>_<tool>`.
+pub async fn connect(name: &str, options: &McpServerOptions) -> anyhow::Result<Vec<Arc<dyn Tool>>> {
+    let connection = async {
+        let client = Arc::new(McpClient::connect(name, options).await?);
+        let tools = client.list_tools().await?;
+        anyhow::Ok(
+            tools
+                .into_iter()
+                .map(|info| {
+                    let read_only = options.read_only_tools.contains(&info.name)
+                        || (options.trusted && info.annotations.read_only_hint);
+                    Arc::new(McpTool {
+                        name: format!("{name}_{}", info.name),
+                        client: client.clone(),
+                        info,
+                        read_only,
+                    }) as Arc<dyn Tool>
+                })
+                .collect(),
+        )
+    };
+    tokio::time::timeout(CONNECT_TIMEOUT, connection)
+        .await
+        .context("The server did not respond in time.")?
+}
+
+#[derive(Debug)]
+struct McpClient {
+    server: String,
+    transport: Transport,
+    next_id: AtomicU64,
+}
+
+impl McpClient {
+    async fn connect(server: &str, options: &McpServerOptions) -> anyhow::Result<Self> {
+        let client = Self {
+            server: server.to_string(),
+            transport: Transport::start(options)?,
+            next_id: AtomicU64::new(1),
+        };
+        client
+            .request(
+                "initialize",
+                json!({
+                    "protocolVersion": PROTOCOL_VERSION,
+                    "capabilities": {},
+                    "clientInfo": {
+                        "name": env!("CARGO_PKG_NAME"),
+                        "version": env!("CARGO_PKG_VERSION"),
+                    },
+                }),
+            )
+            .await?;
+        client
+            .transport
+            .notify(json!({ "jsonrpc": "2.0", "method": "notifications/initialized" }))
+            .await?;
+        Ok(client)
+    }
+
+    async fn request(&self, method: &str, params: Value) -> anyhow::Result<Value> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let message = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params });
+        let mut response = self
+            .transport
+            .request(message, id)
+            .await
+            .with_context(|| format!("MCP request {method} to {} failed.", self.server))?;
+        if let Some(error) = response.get("error") {
+            let message = error["message"].as_str().unwrap_or("unknown error");
+            anyhow::bail!("MCP request {method} to {} failed: {message}", self.server);
+        }
+        Ok(response["result"].take())
+    }
+
+    async fn list_tools(&self) -> anyhow::Result<Vec<ToolInfo>> {
+        let mut tools = Vec::new();
+        let mut cursor: Option<String> = None;
+        loop {
+            let params = match &cursor {
+                Some(cursor) => json!({ "cursor": cursor }),
+                None => json!({}),
+            };
+            let page =
+                serde_json::from_value::<ToolsPage>(self.request("tools/list", params).await?)
+                    .context("Failed to decode the server's tools.")?;
+            tools.extend(page.tools);
+            cursor = page.next_cursor;
+            if cursor.is_none() {
+                return Ok(tools);
+            }
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ToolsPage {
+    tools: Vec<ToolInfo>,
+    next_cursor: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ToolInfo {
+    name: String,
+    #[serde(default)]
+    description: String,
+    input_schema: Value,
+    #[serde(default)]
+    annotations: ToolAnnotations,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ToolAnnotations {
+    #[serde(default)]
+    read_only_hint: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CallToolResult {
+    #[serde(default)]
+    content: Vec<Content>,
+    #[serde(default)]
+    is_error: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Content {
+    Text {
+        text: String,
+    },
+    #[serde(other)]
+    Other,
+}
+
+/// A tool served by an MCP server.
+#[derive(Debug)]
+struct McpTool {
+    /// Prefixed with the server name, so tools from different servers cannot clash.
+    name: String,
+    client: Arc<McpClient>,
+    info: ToolInfo,
+    /// Runs without asking. Servers can claim anything, so their own annotations only count
+    /// when the server is trusted.
+    read_only: bool,
+}
+
+impl Tool for McpTool {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn description(&self) -> &str {
+        &self.info.description
+    }
+
+    fn parameters(&self) -> Value {
+        self.info.input_schema.clone()
+    }
+
+    fn call(&self, arguments: Value) -> BoxFuture<'_, anyhow::Result<String>> {
+        async move {
+            let request = self.client.request(
+                "tools/call",
+                json!({ "name": self.info.name, "arguments": arguments }),
+            );
+            let result = tokio::time::timeout(CALL_TIMEOUT, request)
+                .await
+                .with_context(|| {
+                    format!(
+                        "{} did not respond within {} seconds.",
+                        self.name,
+                        CALL_TIMEOUT.as_secs()
+                    )
+                })??;
+            let result = serde_json::from_value::<CallToolResult>(result)
+                .context("Failed to decode the tool's result.")?;
+            let text = result
+                .content
+                .into_iter()
+                .map(|content| match content {
+                    Content::Text { text } => text,
+                    Content::Other => "[content that is not text]".to_string(),
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+            match result.is_error {
+                true => Err(anyhow::anyhow!(text)),
+                false => Ok(text),
+            }
+        }
+        .boxed()
+    }
+
+    fn side_effect(&self, _arguments: &Value) -> Option<String> {
+        (!self.read_only).then(|| {
+            format!(
+                "call {} on the {} MCP server",
+                self.info.name, self.client.server
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+
+    use super::*;
+
+    /// The `mcp_echo` example, which `cargo test` builds next to the test binaries.
+    fn echo_example() -> PathBuf {
+        let exe = std::env::current_exe().unwrap();
+        let target = exe.parent().and_then(|deps| deps.parent()).unwrap();
+        target
+            .join("examples")
+            .join(format!("mcp_echo{}", std::env::consts::EXE_SUFFIX))
+    }
+
+    #[tokio::test]
+    async fn lists_and_calls_the_echo_example() {
+        let example = echo_example();
+        assert!(
+            example.exists(),
+            "{} is missing, build it with `cargo build --examples`",
+            example.display()
+        );
+        let options = McpServerOptions {
+            command: Some(example.to_string_lossy().into_owned()),
+            args: Vec::new(),
+            env: HashMap::new(),
+            url: None,
+            headers: HashMap::new(),
+            trusted: false,
+            read_only_tools: Vec::new(),
+        };
+
+        let tools = connect("echo", &options).await.unwrap();
+
+        let names = tools.iter().map(|tool| tool.name()).collect::<Vec<_>>();
+        assert_eq!(names, ["echo_echo"]);
+        let echo = &tools[0];
+        assert_eq!(echo.parameters()["required"], json!(["text"]));
+        // The example's read only hint only counts for trusted servers.
+        assert!(echo.side_effect(&json!({})).is_some());
+        // The example pings before answering, so this also checks the ping is answered.
+        let output = echo.call(json!({ "text": "hello" })).await.unwrap();
+        assert_eq!(output, "hello");
+    }
+}
@@ -0,0 +1,253 @@
+use std::process::Stdio as ProcessStdio;
+
+use anyhow::Context;
+use eventsource_stream::Eventsource;
+use futures_util::StreamExt;
+use serde_json::{Value, json};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines},
+    process::{Child, ChildStdin, ChildStdout, Command},
+    sync::Mutex,
+};
+use tracing::trace;
+
+use crate::configuration::McpServerOptions;
+
+const SESSION_HEADER: &str = "Mcp-Session-Id";
+/// JSON-RPC error code for requests the client cannot handle.
+const METHOD_NOT_FOUND: i64 = -32601;
+
+/// Carries JSON-RPC messages to an MCP server and back.
+#[derive(Debug)]
+pub(super) enum Transport {
+    Stdio(Box<Stdio>),
+    Http(Http),
+}
+
+impl Transport {
+    pub(super) fn start(options: &McpServerOptions) -> anyhow::Result<Self> {
+        match (&options.command, &options.url) {
+            (Some(command), None) => {
+                Stdio::spawn(command, options).map(|stdio| Self::Stdio(Box::new(stdio)))
+            }
+            (None, Some(url)) => Http::new(url, options).map(Self::Http),
+            _ => anyhow::bail!("Set either `command` or `url`."),
+        }
+    }
+
+    /// Send a request and wait for the response with the same `id`.
+    pub(super) async fn request(&self, message: Value, id: u64) -> anyhow::Result<Value> {
+        match self {
+            Self::Stdio(stdio) => stdio.request(message, id).await,
+            Self::Http(http) => http.request(message, id).await,
+        }
+    }
+
+    pub(super) async fn notify(&self, message: Value) -> anyhow::Result<()> {
+        match self {
+            Self::Stdio(stdio) => stdio.pipes.lock().await.send(&message).await,
+            Self::Http(http) => http.post(&message).await.map(|_| ()),
+        }
+    }
+}
+
+/// A server run as a subprocess, exchanging one JSON message per line.
+#[derive(Debug)]
+pub(super) struct Stdio {
+    /// Held so the server is killed when the last tool using it is dropped.
+    _child: Child,
+    /// Locked for a whole request, so responses are read by the request waiting for them.
+    pipes: Mutex<Pipes>,
+}
+
+#[derive(Debug)]
+struct Pipes {
+    stdin: ChildStdin,
+    stdout: Lines<BufReader<ChildStdout>>,
+}
+
+impl Stdio {
+    fn spawn(command: &str, options: &McpServerOptions) -> anyhow::Result<Self> {
+        let mut child = Command::new(command)
+            .args(&options.args)
+            .envs(&options.env)
+            .stdin(ProcessStdio::piped())
+            .stdout(ProcessStdio::piped())
+            // The TUI owns the terminal, so the server's logs are dropped.
+            .stderr(ProcessStdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| format!("Failed to start {command}."))?;
+        let pipes = Pipes {
+            stdin: child
+                .stdin
+                .take()
+                .context("Failed to open the server's stdin.")?,
+            stdout: BufReader::new(
+                child
+                    .stdout
+                    .take()
+                    .context("Failed to open the server's stdout.")?,
+            )
+            .lines(),
+        };
+        Ok(Self {
+            _child: child,
+            pipes: Mutex::new(pipes),
+        })
+    }
+
+    async fn request(&self, message: Value, id: u64) -> anyhow::Result<Value> {
+        let mut pipes = self.pipes.lock().await;
+        pipes.send(&message).await?;
+        loop {
+            let line = pipes
+                .stdout
+                .next_line()
+                .await
+                .context("Failed to read from the server.")?
+                .context("The server exited.")?;
+            let Ok(message) = serde_json::from_str::<Value>(&line) else {
+                trace!("Skipping MCP output that is not JSON: {line}");
+                continue;
+            };
+            if is_response(&message, id) {
+                return Ok(message);
+            }
+            match reply(&message) {
+                Some(reply) => pipes.send(&reply).await?,
+                None => trace!("Skipping MCP message: {message}"),
+            }
+        }
+    }
+}
+
+impl Pipes {
+    async fn send(&mut self, message: &Value) -> anyhow::Result<()> {
+        let mut line = message.to_string();
+        line.push('\n');
+        self.stdin
+            .write_all(line.as_bytes())
+            .await
+            .context("Failed to write to the server.")?;
+        self.stdin
+            .flush()
+            .await
+            .context("Failed to write to the server.")
+    }
+}
+
+/// A server reached over streamable HTTP, answering each POST with JSON or an event stream.
+#[derive(Debug)]
+pub(super) struct Http {
+    client: reqwest::Client,
+    url: reqwest::Url,
+    headers: Vec<(String, String)>,
+    /// Assigned by the server when initialising, and sent back with every later request.
+    session: std::sync::Mutex<Option<String>>,
+}
+
+impl Http {
+    fn new(url: &str, options: &McpServerOptions) -> anyhow::Result<Self> {
+        Ok(Self {
+            client: reqwest::Client::new(),
+            url: reqwest::Url::parse(url).with_context(|| format!("Invalid url {url}."))?,
+            headers: options
+                .headers
+                .iter()
+                .map(|(name, value)| (name.clone(), value.clone()))
+                .collect(),
+            session: std::sync::Mutex::new(None),
+        })
+    }
+
+    async fn request(&self, message: Value, id: u64) -> anyhow::Result<Value> {
+        let response = self.post(&message).await?;
+        let is_stream = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|content_type| content_type.to_str().ok())
+            .is_some_and(|content_type| content_type.starts_with("text/event-stream"));
+        if !is_stream {
+            return response
+                .json::<Value>()
+                .await
+                .context("Failed to decode the server's response.");
+        }
+
+        let mut events = response.bytes_stream().eventsource();
+        while let Some(event) = events.next().await {
+            let event = event.context("Failed to read the server's event stream.")?;
+            let Ok(message) = serde_json::from_str::<Value>(&event.data) else {
+                trace!("Skipping MCP event: {}", event.data);
+                continue;
+            };
+            if is_response(&message, id) {
+                return Ok(message);
+            }
+            match reply(&message) {
+                Some(reply) => {
+                    self.post(&reply).await?;
+                }
+                None => trace!("Skipping MCP message: {message}"),
+            }
+        }
+        anyhow::bail!("The server ended its event stream without a response.")
+    }
+
+    async fn post(&self, message: &Value) -> anyhow::Result<reqwest::Response> {
+        let mut request = self
+            .client
+            .post(self.url.clone())
+            .header(
+                reqwest::header::ACCEPT,
+                "application/json, text/event-stream",
+            )
+            .json(message);
+        for (name, value) in &self.headers {
+            request = request.header(name, value);
+        }
+        let session = self.session.lock().ok().and_then(|session| session.clone());
+        if let Some(session) = session {
+            request = request.header(SESSION_HEADER, session);
+        }
+
+        let response = request
+            .send()
+            .await
+            .with_context(|| format!("Failed to reach {}.", self.url))?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            anyhow::bail!("The server responded with {status}. {body}");
+        }
+        if let Some(session) = response
+            .headers()
+            .get(SESSION_HEADER)
+            .and_then(|session| session.to_str().ok())
+            && let Ok(mut current) = self.session.lock()
+        {
+            *current = Some(session.to_string());
+        }
+        Ok(response)
+    }
+}
+
+fn is_response(message: &Value, id: u64) -> bool {
+    message["id"].as_u64() == Some(id) && message.get("method").is_none()
+}
+
+/// The answer to a request the server sent while a response was awaited. Pings are answered,
+/// as servers may drop clients that ignore them, and anything else is declined.
+fn reply(message: &Value) -> Option<Value> {
+    let id = message.get("id")?;
+    let method = message.get("method")?.as_str()?;
+    Some(match method {
+        "ping" => json!({ "jsonrpc": "2.0", "id": id, "result": {} }),
+        _ => json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": { "code": METHOD_NOT_FOUND, "message": format!("{method} is not supported.") },
+        }),
+    })
+}
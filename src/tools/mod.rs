@@ -0,0 +1,146 @@
+mod clock;
+mod custom;
+mod fs;
+pub mod mcp;
+mod shell;
+
+use std::sync::Arc;
+
+use futures_util::future::BoxFuture;
+
+use crate::configuration::Configuration;
+
+/// Longest output handed back to the model from a single call.
+const MAX_OUTPUT_BYTES: usize = 32 * 1024;
+
+/// A function the model can ask to call, run locally with the arguments it chose.
+pub trait Tool: Send + Sync + std::fmt::Debug {
+    /// Identifier the model calls the tool by.
+    fn name(&self) -> &str;
+    /// Tells the model what the tool does and when to use it.
+    fn description(&self) -> &str;
+    /// JSON schema of the arguments object.
+    fn parameters(&self) -> serde_json::Value;
+    /// Run the tool, returning the text handed back to the model.
+    fn call(&self, arguments: serde_json::Value) -> BoxFuture<'_, anyhow::Result<String>>;
+    /// What the call would change, e.g. "run `cargo test`", when the user must allow it first.
+    fn side_effect(&self, _arguments: &serde_json::Value) -> Option<String> {
+        None
+    }
+}
+
+/// A tool call waiting for the user to allow it.
+#[derive(Debug, Clone)]
+pub struct ApprovalRequest {
+    pub tool: String,
+    pub action: String,
+}
+
+/// Asks the user whether a tool call with side effects may run.
+pub trait Approver: Send + Sync + std::fmt::Debug {
+    fn approve(&self, request: ApprovalRequest) -> BoxFuture<'_, bool>;
+}
+
+/// Every tool the model could be offered. Which ones it is offered is chosen per conversation
+/// with [`LLMTools`](crate::provider::LLMTools). The default toolbox is empty.
+#[derive(Debug, Clone, Default)]
+pub struct Toolbox {
+    tools: Vec<Arc<dyn Tool>>,
+    /// Without an approver, calls with side effects are refused.
+    approver: Option<Arc<dyn Approver>>,
+}
+
+impl Toolbox {
+    /// The tools declared in the config, followed by the built in ones. A declared tool
+    /// replaces a built in one with the same name.
+    pub fn new(config: &Configuration) -> Self {
+        let tool_options = config.tools.as_ref();
+        let allowed_commands = tool_options
+            .and_then(|tool_options| tool_options.allowed_commands.clone())
+            .unwrap_or_default();
+        let custom = tool_options
+            .map(|tool_options| tool_options.custom.clone())
+            .unwrap_or_default()
+            .into_iter()
+            .map(|options| Arc::new(custom::CommandTool::new(options)) as Arc<dyn Tool>);
+        let built_in: [Arc<dyn Tool>; 6] = [
+            Arc::new(clock::CurrentTime),
+            Arc::new(fs::ReadFile),
+            Arc::new(fs::ListDirectory),
+            Arc::new(fs::Grep),
+            Arc::new(fs::WriteFile),
+            Arc::new(shell::RunShell::new(allowed_commands)),
+        ];
+        let mut toolbox = Self::default();
+        for tool in custom.chain(built_in) {
+            if toolbox.get(tool.name()).is_none() {
+                toolbox.tools.push(tool);
+            }
+        }
+        toolbox
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn Tool> {
+        self.tools
+            .iter()
+            .find(|tool| tool.name() == name)
+            .map(|tool| tool.as_ref())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &dyn Tool> {
+        self.tools.iter().map(|tool| tool.as_ref())
+    }
+
+    /// Add tools from outside the built in set, e.g. those of MCP servers. Tools with the name
+    /// of one already in the toolbox are left out, and their names returned.
+    pub fn extend(&mut self, tools: impl IntoIterator<Item = Arc<dyn Tool>>) -> Vec<String> {
+        let mut skipped = Vec::new();
+        for tool in tools {
+            match self.get(tool.name()) {
+                Some(_) => skipped.push(tool.name().to_string()),
+                None => self.tools.push(tool),
+            }
+        }
+        skipped
+    }
+
+    pub fn set_approver(&mut self, approver: Arc<dyn Approver>) {
+        self.approver = Some(approver);
+    }
+
+    /// Run `tool`, first asking the user when the call has side effects.
+    pub async fn run(
+        &self,
+        tool: &dyn Tool,
+        arguments: serde_json::Value,
+    ) -> anyhow::Result<String> {
+        if let Some(action) = tool.side_effect(&arguments) {
+            let Some(approver) = &self.approver else {
+                anyhow::bail!(
+                    "Not allowed to {action} without asking the user, and there is no terminal to ask on."
+                );
+            };
+            let request = ApprovalRequest {
+                tool: tool.name().to_string(),
+                action: action.clone(),
+            };
+            if !approver.approve(request).await {
+                anyhow::bail!("The user did not allow you to {action}.");
+            }
+        }
+        tool.call(arguments).await.map(truncate_output)
+    }
+}
+
+fn truncate_output(mut output: String) -> String {
+    if output.len() <= MAX_OUTPUT_BYTES {
+        return output;
+    }
+    let mut end = MAX_OUTPUT_BYTES;
+    while !output.is_char_boundary(end) {
+        end -= 1;
+    }
+    output.truncate(end);
+    output.push_str("\n[output truncated]");
+    output
+}
@@ -0,0 +1,151 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use futures_util::{FutureExt, future::BoxFuture};
+use serde::Deserialize;
+use serde_json::json;
+
+use super::Tool;
+
+/// Characters that chain, pipe, substitute or redirect, so an allowed prefix could hide another
+/// command. Commands containing them always ask.
+const SHELL_OPERATORS: &[char] = &[';', '&', '|', '`', '$', '>', '<', '(', ')', '\n'];
+/// How long a command may run before it is killed.
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Runs a command with `sh` in the workspace.
+#[derive(Debug)]
+pub(super) struct RunShell {
+    /// Commands that run without asking, with or without further arguments.
+    allowed_commands: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct ShellArguments {
+    command: String,
+}
+
+impl RunShell {
+    pub(super) fn new(allowed_commands: Vec<String>) -> Self {
+        Self { allowed_commands }
+    }
+
+    /// Whether `command` is an allowed command, e.g. `git log -5` when `git log` is allowed.
+    fn allowed(&self, command: &str) -> bool {
+        let command = command.trim();
+        !command.contains(SHELL_OPERATORS)
+            && self.allowed_commands.iter().any(|allowed| {
+                command
+                    .strip_prefix(allowed.trim())
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with(' '))
+            })
+    }
+}
+
+impl Tool for RunShell {
+    fn name(&self) -> &str {
+        "run_shell"
+    }
+
+    fn description(&self) -> &str {
+        "Run a shell command in the workspace and return its exit status, stdout and stderr."
+    }
+
+    fn parameters(&self) -> serde_json::Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "command": { "type": "string", "description": "Command line run with `sh -c`." }
+            },
+            "required": ["command"]
+        })
+    }
+
+    fn call(&self, arguments: serde_json::Value) -> BoxFuture<'_, anyhow::Result<String>> {
+        async move {
+            let arguments = serde_json::from_value::<ShellArguments>(arguments)
+                .context("Invalid arguments for run_shell.")?;
+            // Killed if it times out or the response is stopped while the command runs.
+            let output = tokio::process::Command::new("sh")
+                .arg("-c")
+                .arg(&arguments.command)
+                .stdin(std::process::Stdio::null())
+                .kill_on_drop(true)
+                .output();
+            let output = tokio::time::timeout(COMMAND_TIMEOUT, output)
+                .await
+                .with_context(|| {
+                    format!(
+                        "{} did not finish within {} seconds.",
+                        arguments.command,
+                        COMMAND_TIMEOUT.as_secs()
+                    )
+                })?
+                .with_context(|| format!("Failed to run {}.", arguments.command))?;
+            Ok(format!(
+                "{}\n\nstdout:\n{}\n\nstderr:\n{}",
+                output.status,
+                String::from_utf8_lossy(&output.stdout).trim_end(),
+                String::from_utf8_lossy(&output.stderr).trim_end()
+            ))
+        }
+        .boxed()
+    }
+
+    fn side_effect(&self, arguments: &serde_json::Value) -> Option<String> {
+        let command = arguments["command"].as_str().unwrap_or_default();
+        (!self.allowed(command)).then(|| format!("run `{command}`"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shell() -> RunShell {
+        RunShell::new(vec!["git status".to_string(), "cargo test".to_string()])
+    }
+
+    #[test]
+    fn allows_listed_commands_with_arguments() {
+        assert!(shell().allowed("git status"));
+        assert!(shell().allowed("  git status --short "));
+        assert!(shell().allowed("cargo test -- --nocapture"));
+    }
+
+    #[test]
+    fn refuses_commands_that_only_share_a_prefix() {
+        assert!(!shell().allowed("git statusx"));
+        assert!(!shell().allowed("git"));
+        assert!(!shell().allowed("git push"));
+    }
+
+    #[test]
+    fn refuses_shell_operators_after_an_allowed_prefix() {
+        for command in [
+            "git status; rm -rf /",
+            "git status && rm -rf /",
+            "git status || rm -rf /",
+            "git status | sh",
+            "git status & rm -rf /",
+            "git status $(rm -rf /)",
+            "git status `rm -rf /`",
+            "git status > /etc/passwd",
+            "git status < /dev/zero",
+            "git status\nrm -rf /",
+        ] {
+            assert!(!shell().allowed(command), "{command:?} was allowed");
+        }
+    }
+
+    #[test]
+    fn asks_before_commands_that_are_not_allowed() {
+        let side_effect = shell().side_effect(&json!({ "command": "rm -rf target" }));
+
+        assert_eq!(side_effect.as_deref(), Some("run `rm -rf target`"));
+        assert_eq!(
+            shell().side_effect(&json!({ "command": "git status" })),
+            None
+        );
+    }
+}